@@ -0,0 +1,328 @@
+//! Activity log for the OpenAI Proxy Carousel
+//!
+//! Writes one JSON line per proxied request to `acivity_logging_path`. When the file
+//! grows past the configured size it is compressed to `<name>.<timestamp>.gz` next to
+//! it, and the oldest compressed files beyond the configured count are removed.
+//! Request and response bodies pass through the [`Redactor`] before they are written.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use chrono::{DateTime, Utc};
+use flate2::{write::GzEncoder, Compression};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::models::{usage::TokenUsage, IdentityKind};
+use crate::redaction::Redactor;
+
+/// Number of records that may wait for the writer before new ones are dropped
+const LOGGER_CAPACITY: usize = 1024;
+
+/// Rotate the log when it grows past this size unless configured otherwise
+pub const DEFAULT_MAX_BYTES: u64 = 100 * 1024 * 1024;
+/// Keep this many compressed files unless configured otherwise
+pub const DEFAULT_MAX_FILES: usize = 10;
+
+/// Durations of the phases of a request, in milliseconds since it was received
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ActivityTimings {
+    /// Until the upstream response headers arrived
+    pub upstream_ms: Option<u64>,
+    /// Until the first chunk of a streamed response arrived
+    pub first_chunk_ms: Option<u64>,
+    /// Until the response was fully sent
+    pub total_ms: u64,
+}
+
+/// A single line of the activity log
+#[derive(Debug, Clone, Serialize)]
+pub struct ActivityRecord {
+    pub request_id: String,
+    /// The `x-request-id` of the upstream response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_request_id: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    /// Fingerprint of the client access key or JWT subject, `None` for unauthenticated requests
+    pub client_key_id: Option<String>,
+    pub client_kind: Option<IdentityKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_owner: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub client_groups: Vec<String>,
+    pub model: Option<String>,
+    pub status: u16,
+    pub stream: bool,
+    pub timings: ActivityTimings,
+    pub usage: Option<TokenUsage>,
+    pub request: Value,
+    /// The response body; streamed responses are reassembled into a `chat.completion`
+    pub response: Value,
+    /// Why a streamed response ended early
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Converts a body to JSON when possible, falling back to a (lossy) string
+pub fn body_to_value(body: &[u8]) -> Value {
+    if body.is_empty() {
+        return Value::Null;
+    }
+
+    serde_json::from_slice(body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(body).into_owned()))
+}
+
+enum LoggerMessage {
+    Record(Box<ActivityRecord>),
+    /// Answered once every record queued before it is written
+    Flush(oneshot::Sender<()>),
+}
+
+/// Queues activity records for a background writer
+#[derive(Clone)]
+pub struct ActivityLogger {
+    sender: mpsc::Sender<LoggerMessage>,
+    redactor: Arc<RwLock<Arc<Redactor>>>,
+}
+
+impl ActivityLogger {
+    /// Opens the log at `path` and starts the background writer
+    pub fn spawn(
+        path: &str,
+        max_bytes: u64,
+        max_files: usize,
+        redactor: Arc<Redactor>,
+    ) -> io::Result<Self> {
+        let mut writer = RotatingWriter::open(PathBuf::from(path), max_bytes, max_files)?;
+        let (sender, mut receiver) = mpsc::channel::<LoggerMessage>(LOGGER_CAPACITY);
+        let redactor = Arc::new(RwLock::new(redactor));
+        let current_redactor = redactor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            while let Some(message) = receiver.blocking_recv() {
+                let mut record = match message {
+                    LoggerMessage::Record(record) => record,
+                    LoggerMessage::Flush(done) => {
+                        _ = done.send(());
+                        continue;
+                    }
+                };
+                let redactor = current_redactor.read().unwrap().clone();
+                redactor.redact_value(&mut record.request);
+                redactor.redact_value(&mut record.response);
+
+                let mut line = match serde_json::to_vec(&*record) {
+                    Ok(line) => line,
+                    Err(e) => {
+                        log::error!("failed to serialize activity record: {}", e);
+                        continue;
+                    }
+                };
+                line.push(b'\n');
+
+                if let Err(e) = writer.write_line(&line) {
+                    log::error!("failed to write activity log: {}", e);
+                }
+            }
+        });
+
+        Ok(Self { sender, redactor })
+    }
+
+    /// Replaces the redactor applied to records that are not written yet
+    pub fn set_redactor(&self, redactor: Arc<Redactor>) {
+        *self.redactor.write().unwrap() = redactor;
+    }
+
+    /// Queues a record without waiting; the record is dropped if the writer is behind
+    pub fn log(&self, record: ActivityRecord) {
+        if let Err(e) = self.sender.try_send(LoggerMessage::Record(Box::new(record))) {
+            log::warn!("activity record dropped: {}", e);
+        }
+    }
+
+    /// Waits until the records queued so far are written
+    pub async fn flush(&self) {
+        let (done, written) = oneshot::channel();
+        if self.sender.send(LoggerMessage::Flush(done)).await.is_ok() {
+            _ = written.await;
+        }
+    }
+}
+
+/// Appends lines to a file, compressing it away once it exceeds `max_bytes`
+struct RotatingWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    size: u64,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl RotatingWriter {
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            file: BufWriter::new(file),
+            size,
+            max_bytes,
+            max_files,
+        })
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line)?;
+        self.file.flush()?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let file_name = self.file_name();
+        let rotated = self.path.with_file_name(format!(
+            "{}.{}.gz",
+            file_name,
+            Utc::now().format("%Y%m%dT%H%M%S%3f")
+        ));
+        compress(&self.path, &rotated)?;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.size = 0;
+
+        if let Err(e) = self.prune() {
+            log::warn!("failed to remove old activity logs: {}", e);
+        }
+
+        Ok(())
+    }
+
+    /// Removes the oldest compressed logs beyond `max_files`
+    fn prune(&self) -> io::Result<()> {
+        let prefix = format!("{}.", self.file_name());
+        let directory = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        let mut rotated: Vec<PathBuf> = fs::read_dir(&directory)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".gz"))
+            })
+            .collect();
+        rotated.sort();
+
+        let excess = rotated.len().saturating_sub(self.max_files);
+        for path in rotated.into_iter().take(excess) {
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+
+    fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+fn compress(source: &Path, target: &Path) -> io::Result<()> {
+    let mut input = File::open(source)?;
+    let mut encoder = GzEncoder::new(File::create(target)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, time::Duration};
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    fn rotated_files(directory: &Path) -> Vec<PathBuf> {
+        let mut rotated: Vec<PathBuf> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "gz"))
+            .collect();
+        rotated.sort();
+        rotated
+    }
+
+    fn decompress(path: &Path) -> String {
+        let mut content = String::new();
+        GzDecoder::new(File::open(path).unwrap()).read_to_string(&mut content).unwrap();
+        content
+    }
+
+    #[test]
+    fn rotates_compresses_and_prunes() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("logs").join("activity.jsonl");
+        let mut writer = RotatingWriter::open(path.clone(), 20, 2).unwrap();
+
+        // Two 10 byte lines fit in a file, so every other line rotates it
+        for line in 0..10 {
+            writer.write_line(format!("record-{:02}\n", line).as_bytes()).unwrap();
+            // Rotated files are named by the millisecond
+            std::thread::sleep(Duration::from_millis(2));
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "record-08\nrecord-09\n");
+        let rotated = rotated_files(path.parent().unwrap());
+        assert_eq!(rotated.len(), 2);
+        assert!(rotated.iter().all(|file| {
+            file.file_name().unwrap().to_str().unwrap().starts_with("activity.jsonl.")
+        }));
+        assert_eq!(decompress(&rotated[0]), "record-04\nrecord-05\n");
+        assert_eq!(decompress(&rotated[1]), "record-06\nrecord-07\n");
+    }
+
+    #[test]
+    fn continues_an_existing_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("activity.jsonl");
+        fs::write(&path, "record-00\n").unwrap();
+
+        let mut writer = RotatingWriter::open(path.clone(), 20, 2).unwrap();
+        writer.write_line(b"record-01\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "record-00\nrecord-01\n");
+        assert!(rotated_files(directory.path()).is_empty());
+
+        writer.write_line(b"record-02\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "record-02\n");
+        assert_eq!(decompress(&rotated_files(directory.path())[0]), "record-00\nrecord-01\n");
+    }
+}
@@ -0,0 +1,93 @@
+//! Command-line interface for the OpenAI Proxy Carousel
+
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use log::LevelFilter;
+
+use crate::models::config::ConfigOverrides;
+
+/// Commented configuration with the default settings, printed by `print-default-config`
+pub const DEFAULT_CONFIG: &str = include_str!("default_config.toml");
+
+#[derive(Debug, Parser)]
+#[command(name = "openai-proxy", version, about = "Proxy for the OpenAI API with key rotation")]
+pub struct Cli {
+    /// Configuration file (TOML or JSON) instead of config.toml / config.json
+    #[arg(long, short, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on, overrides `host`
+    #[arg(long, global = true)]
+    pub host: Option<String>,
+    /// Port to listen on, overrides `port`
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+    /// Log level (off, error, warn, info, debug, trace), overrides `log_level`
+    #[arg(long, global = true)]
+    pub log_level: Option<LevelFilter>,
+    /// Same as the `check-config` command
+    #[arg(long, hide = true)]
+    pub check_config: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Runs the proxy (default)
+    Serve,
+    /// Validates the configuration and exits with status 1 when it is invalid
+    CheckConfig,
+    /// Prints a hashed `access_keys` entry, generating a new key when none is given
+    HashKey {
+        /// The key to hash, `-` reads it from stdin
+        key: Option<String>,
+    },
+    /// Encrypts a secret into an `enc:v1:` value with the master key
+    EncryptSecret {
+        /// The secret to encrypt; read from stdin when omitted or `-`
+        secret: Option<String>,
+    },
+    /// Prints a new random master key for `SECRETS_MASTER_KEY`
+    GenerateMasterKey,
+    /// Prints a commented configuration with the default settings
+    PrintDefaultConfig,
+}
+
+impl Cli {
+    /// Returns the command to run, `serve` when none is given
+    pub fn command(&self) -> &Command {
+        match &self.command {
+            Some(command) => command,
+            None if self.check_config => &Command::CheckConfig,
+            None => &Command::Serve,
+        }
+    }
+
+    pub fn overrides(&self) -> ConfigOverrides {
+        ConfigOverrides {
+            path: self.config.clone(),
+            host: self.host.clone(),
+            port: self.port,
+            log_level: self.log_level,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{providers::{Format, Toml}, Figment};
+
+    use super::DEFAULT_CONFIG;
+    use crate::models::config::Config;
+
+    #[test]
+    fn default_config_is_valid() {
+        let config: Config = Figment::from(Toml::string(DEFAULT_CONFIG)).extract().unwrap();
+
+        assert_eq!(config.check_auth(), Ok(()));
+        if let Err(errors) = config.validate() {
+            panic!("{:?}", errors.iter().map(ToString::to_string).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! Configuration hot reload for the OpenAI Proxy Carousel
+//!
+//! The configuration files are watched for changes and re-read on SIGHUP. A new
+//! configuration is validated before anything is swapped; when it is invalid the
+//! current one stays in effect. Requests take a snapshot of the configuration when
+//! they start, so in-flight requests finish on the configuration they started with.
+
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use crate::handlers::keys::KEY_CHANGES;
+use crate::logger;
+use crate::models::config::Config;
+use crate::redaction::Redactor;
+use crate::state::State as ProxyState;
+
+/// Editors often write a file in several steps; wait for them to settle before reloading
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Logs the changed settings that only take effect after a restart
+fn warn_restart_required(current: &Config, new: &Config) {
+    let mut changed = Vec::new();
+    if current.host != new.host || current.port != new.port {
+        changed.push("host/port");
+    }
+    if current.tls != new.tls {
+        changed.push("tls");
+    }
+    if current.listeners != new.listeners {
+        changed.push("listeners");
+    }
+    if current.usage_db_path != new.usage_db_path {
+        changed.push("usage_db_path");
+    }
+    if current.acivity_logging_path != new.acivity_logging_path
+        || current.activity_log_max_bytes != new.activity_log_max_bytes
+        || current.activity_log_max_files != new.activity_log_max_files
+    {
+        changed.push("activity log");
+    }
+    if current.auth.jwt != new.auth.jwt {
+        changed.push("auth.jwt");
+    }
+    let logging_output = |config: &Config| {
+        let logging = &config.logging;
+        (logging.format, logging.file().map(str::to_string), logging.rotation, logging.max_files)
+    };
+    if logging_output(current) != logging_output(new) {
+        changed.push("logging output");
+    }
+    if current.telemetry != new.telemetry {
+        changed.push("telemetry");
+    }
+    if current.upstream_connect_timeout_secs != new.upstream_connect_timeout_secs
+        || current.upstream_timeout_secs != new.upstream_timeout_secs
+    {
+        changed.push("upstream timeouts");
+    }
+
+    if !changed.is_empty() {
+        log::warn!("changes to {} take effect after a restart", changed.join(", "));
+    }
+}
+
+/// Keeps the current `auth.jwt` in `new`: the authorization middleware reads it, but
+/// the verifier for it is only built on startup
+fn keep_jwt(current: &Config, new: &mut Config) -> Result<(), String> {
+    new.auth.jwt = current.auth.jwt.clone();
+    new.check_auth().map_err(|errors| errors.join("; "))
+}
+
+/// Reads and validates the configuration, then swaps it in
+pub async fn reload(state: &ProxyState) -> Result<(), String> {
+    let _guard = KEY_CHANGES.lock().await;
+
+    let mut config = Config::load().map_err(|e| e.to_string())?;
+    let redactor = Redactor::new(&config).map_err(|e| e.to_string())?;
+
+    {
+        let mut current = state.config.write().unwrap();
+        warn_restart_required(&current, &config);
+        keep_jwt(&current, &mut config)?;
+
+        logger::set_levels(config.log_level, &config.logging);
+        if let Some(activity_logger) = &state.activity_logger {
+            activity_logger.set_redactor(Arc::new(redactor));
+        }
+        // Swapped under the config lock so no request sees new keys with the old config
+        state.key_manager.write().unwrap().set_keys(config.api_keys.clone());
+        *current = config;
+    }
+
+    log::info!("configuration reloaded");
+    Ok(())
+}
+
+async fn reload_or_keep(state: &ProxyState) {
+    if let Err(e) = reload(state).await {
+        log::error!("configuration reload failed, keeping the current configuration: {}", e);
+    }
+}
+
+/// Watches the configuration files and listens for SIGHUP, reloading on either
+pub fn spawn_config_watcher(state: ProxyState) {
+    let (sender, mut receiver) = mpsc::channel::<()>(1);
+
+    let files = Config::source_files();
+    let directories: Vec<PathBuf> = files
+        .iter()
+        .map(|file| match file.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        })
+        .collect();
+    let file_sender = sender.clone();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        let relevant = event.paths.iter().any(|path| {
+            path.file_name()
+                .is_some_and(|name| files.iter().any(|file| file.file_name() == Some(name)))
+        });
+        if relevant && !event.kind.is_access() {
+            // A reload is already pending when the channel is full
+            _ = file_sender.try_send(());
+        }
+    });
+
+    // Watch the directories rather than the files, editors replace files on save
+    let watcher: Option<RecommendedWatcher> = match watcher {
+        Ok(mut watcher) => {
+            let watched = directories
+                .iter()
+                .try_for_each(|directory| watcher.watch(directory, RecursiveMode::NonRecursive));
+            match watched {
+                Ok(()) => Some(watcher),
+                Err(e) => {
+                    log::error!("failed to watch the configuration files: {}", e);
+                    None
+                }
+            }
+        }
+        Err(e) => {
+            log::error!("failed to watch the configuration files: {}", e);
+            None
+        }
+    };
+
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                log::error!("failed to listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            log::info!("SIGHUP received");
+            _ = sender.try_send(());
+        }
+    });
+
+    tokio::spawn(async move {
+        // Keep the watcher alive as long as the task runs
+        let _watcher = watcher;
+
+        while receiver.recv().await.is_some() {
+            tokio::time::sleep(DEBOUNCE).await;
+            while receiver.try_recv().is_ok() {}
+
+            reload_or_keep(&state).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{providers::{Format, Toml}, Figment};
+
+    use super::*;
+
+    fn config(host: &str, jwt: &str) -> Config {
+        Figment::from(Toml::string(&format!(
+            r#"
+            version = "test"
+            host = "{host}"
+            port = 8080
+            base_url = "https://api.openai.com/v1"
+            api_keys = ["sk-a"]
+            access_keys = []
+            {jwt}
+            "#
+        )))
+        .extract()
+        .unwrap()
+    }
+
+    #[test]
+    fn keeps_the_jwt_settings_until_a_restart() {
+        let jwt = r#"auth.jwt = { jwks_url = "https://idp.test/jwks.json", issuer = "https://idp.test/", audience = "openai-proxy" }"#;
+
+        // Without a verifier for it, the new `auth.jwt` must not make credentials required
+        let mut new = config("127.0.0.1", jwt);
+        keep_jwt(&config("127.0.0.1", ""), &mut new).unwrap();
+        assert!(new.auth.jwt.is_none());
+        assert!(new.lacks_credentials(&new.listeners()[0]));
+
+        let mut new = config("0.0.0.0", "");
+        keep_jwt(&config("0.0.0.0", jwt), &mut new).unwrap();
+        assert!(new.auth.jwt.is_some());
+
+        // A public listener is only allowed because of the `auth.jwt` that is not applied
+        let mut new = config("0.0.0.0", jwt);
+        let result = keep_jwt(&config("127.0.0.1", ""), &mut new);
+        assert_eq!(
+            result,
+            Err("auth.mode is \"required\" on 0.0.0.0:8080 but neither access_keys, auth.jwt nor tls.client_auth are configured".to_string())
+        );
+    }
+}
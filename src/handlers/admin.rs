@@ -0,0 +1,92 @@
+use axum::{
+    extract::{rejection::QueryRejection, Query, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+
+use crate::models::config::parse_timestamp;
+use crate::models::ProxyError;
+use crate::state::State as ProxyState;
+use crate::storage::{UsageFilter, UsageGrouping};
+
+/// Query parameters accepted by the `/admin/usage/*` endpoints
+///
+/// `from` and `to` accept either a date (`2025-01-31`) or an RFC 3339 timestamp.
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub key: Option<String>,
+    pub model: Option<String>,
+}
+
+impl UsageQuery {
+    fn into_filter(self) -> Result<UsageFilter, ProxyError> {
+        let parse = |name: &'static str, value: Option<String>| match value {
+            Some(value) => parse_timestamp(&value)
+                .map(Some)
+                .ok_or_else(|| ProxyError::invalid_param(name, format!("Invalid `{}` value: {}", name, value))),
+            None => Ok(None),
+        };
+
+        Ok(UsageFilter {
+            from: parse("from", self.from)?,
+            to: parse("to", self.to)?,
+            access_key: self.key,
+            model: self.model,
+        })
+    }
+}
+
+async fn usage_report(
+    state: ProxyState,
+    grouping: UsageGrouping,
+    query: Result<Query<UsageQuery>, QueryRejection>,
+) -> Response {
+    let Some(store) = state.usage_store.clone() else {
+        return ProxyError::NotFound {
+            code: "usage_store_disabled",
+            message: "Usage store is not configured".to_string(),
+        }
+        .into_response();
+    };
+
+    let filter = match query.map_err(ProxyError::from).and_then(|Query(query)| query.into_filter()) {
+        Ok(filter) => filter,
+        Err(e) => return e.into_response(),
+    };
+
+    match store.aggregate(grouping, &filter).await {
+        Ok(data) => Json(serde_json::json!({
+            "group_by": grouping.as_str(),
+            "data": data,
+        }))
+        .into_response(),
+        Err(e) => {
+            log::error!("usage query failed: {}", e);
+            ProxyError::Internal { code: "usage_query_failed", message: e.to_string() }.into_response()
+        }
+    }
+}
+
+pub async fn usage_by_key_handler(
+    State(state): State<ProxyState>,
+    query: Result<Query<UsageQuery>, QueryRejection>,
+) -> Response {
+    usage_report(state, UsageGrouping::AccessKey, query).await
+}
+
+pub async fn usage_by_model_handler(
+    State(state): State<ProxyState>,
+    query: Result<Query<UsageQuery>, QueryRejection>,
+) -> Response {
+    usage_report(state, UsageGrouping::Model, query).await
+}
+
+pub async fn usage_by_day_handler(
+    State(state): State<ProxyState>,
+    query: Result<Query<UsageQuery>, QueryRejection>,
+) -> Response {
+    usage_report(state, UsageGrouping::Day, query).await
+}
@@ -0,0 +1,236 @@
+use std::time::Instant;
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use futures_util::stream::StreamExt;
+use http_body_util::BodyExt;
+use tracing::Instrument;
+
+use crate::key_manager::fingerprint;
+use crate::middleware::request_id::{RequestId, Scoped, REQUEST_ID_HEADER, UPSTREAM_REQUEST_ID_HEADER};
+use crate::models::{ClientIdentity, ProxyError};
+use crate::state::State as ProxyState;
+use crate::telemetry;
+use super::exchange::ExchangeTracker;
+
+/// Follows the end of a server-sent event stream across chunks, to tell whether it
+/// stopped between two events
+#[derive(Debug, Default)]
+struct EventBoundary {
+    /// The last bytes of the stream, enough for the longest event separator
+    tail: Vec<u8>,
+}
+
+impl EventBoundary {
+    const SEPARATOR_LENGTH: usize = b"\r\n\r\n".len();
+
+    fn push(&mut self, chunk: &[u8]) {
+        self.tail.extend_from_slice(&chunk[chunk.len().saturating_sub(Self::SEPARATOR_LENGTH)..]);
+        let excess = self.tail.len().saturating_sub(Self::SEPARATOR_LENGTH);
+        self.tail.drain(..excess);
+    }
+
+    fn at_event_end(&self) -> bool {
+        self.tail.is_empty()
+            || [&b"\n\n"[..], b"\r\r", b"\r\n\r\n"].iter().any(|separator| self.tail.ends_with(separator))
+    }
+}
+
+pub async fn chat_completions_handler(
+    State(state): State<ProxyState>,
+    identity: Option<Extension<ClientIdentity>>,
+    Extension(RequestId(request_id)): Extension<RequestId>,
+    mut headers: HeaderMap,
+    request: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    log::info!("/chat/completions");
+
+    let started = Instant::now();
+    let client = &state.upstream;
+
+    headers.remove("authorization");
+    headers.remove("host");
+    let api_key = tracing::info_span!("select_upstream_key").in_scope(|| state.key_manager.write().unwrap().get_key());
+    let Some(api_key) = api_key else {
+        log::error!("no usable upstream API key");
+        return ProxyError::NoUpstreamKey.into_response();
+    };
+    let auth_value = match format!("Bearer {}", api_key).parse() {
+        Ok(auth_value) => auth_value,
+        Err(_) => {
+            log::error!("upstream key {} is not a valid header value", fingerprint(&api_key));
+            return ProxyError::Internal {
+                code: "invalid_upstream_key",
+                message: "The upstream API key is not a valid header value".to_string(),
+            }
+            .into_response();
+        }
+    };
+    headers.append("authorization", auth_value);
+
+    let base_url = state.config.read().unwrap().base_url.clone();
+    let collected_body = match  request.into_body().collect().await {
+        Ok(collection) => collection.to_bytes(),
+        Err(_) => Bytes::new(),
+    };
+
+    let mut tracker = ExchangeTracker::new(
+        &state,
+        request_id.clone(),
+        started,
+        identity.map(|Extension(identity)| identity),
+        &api_key,
+        collected_body.clone(),
+    );
+
+    let upstream_span = tracing::info_span!(
+        "upstream_request",
+        otel.kind = "client",
+        upstream.key = %fingerprint(&api_key),
+        http.response.status_code = tracing::field::Empty,
+    );
+    telemetry::inject(&upstream_span, &mut headers);
+
+    let req = client
+        .post(format!("{base_url}/chat/completions",))
+        .headers(headers)
+        .body(collected_body);
+    let data = match req.build() {
+        Ok(data) => data,
+        Err(e) => {
+            log::error!("failed to build the upstream request: {}", e);
+            return ProxyError::Internal { code: "invalid_upstream_request", message: e.to_string() }.into_response();
+        }
+    };
+    let res = match client.execute(data).instrument(upstream_span.clone()).await {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{}", e);
+            return ProxyError::from(e).into_response();
+        },
+    };
+    let is_stream = if let Some(header_value) = res.headers().get("content-type") {
+        match header_value.to_str() {
+            Ok(s) => s == "text/event-stream",
+            Err(_) => false,
+        }
+    } else { false };
+    let mut headers = res.headers().clone();
+    let status = res.status();
+    // The request ID middleware returns ours as `x-request-id`
+    let upstream_request_id = headers.remove(REQUEST_ID_HEADER);
+    if let Some(value) = &upstream_request_id {
+        headers.insert(UPSTREAM_REQUEST_ID_HEADER, value.clone());
+    }
+    tracker.upstream_responded(
+        status,
+        is_stream,
+        upstream_request_id.and_then(|value| value.to_str().ok().map(str::to_string)),
+    );
+    upstream_span.record("http.response.status_code", status.as_u16());
+
+    log::info!("---!!! response status: {}", status.as_str());
+
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        state.metrics.rate_limited.with_label_values(&[fingerprint(&api_key).as_str()]).inc();
+        state.metrics.key_switches.inc();
+        // switch to next key
+        state.key_manager.write().unwrap().switch_key();
+    }
+
+    let body = if is_stream {
+        log::debug!("--->>> stream response");
+        let stream_span = tracing::info_span!(
+            parent: &upstream_span,
+            "stream_response",
+            chunks = tracing::field::Empty,
+            otel.status_code = tracing::field::Empty,
+            otel.status_message = tracing::field::Empty,
+        );
+        let stream = async_stream::stream! {
+            // The span ends when the stream is finished or dropped
+            let stream_span = stream_span;
+            let mut tracker = tracker;
+            let mut stream = res.bytes_stream();
+            let mut chunks = 0u64;
+            let mut boundary = EventBoundary::default();
+
+            while let Some(item) = stream.next().await {
+                log::trace!("---+++ chank");
+                let item = match item {
+                    Ok(item) => item,
+                    Err(e) => {
+                        // The status is already sent, so the client learns about the failure
+                        // from a final error event, as OpenAI reports errors mid-stream
+                        let error = ProxyError::UpstreamInterrupted(e.to_string());
+                        log::error!("upstream stream failed after {} chunks: {}", chunks, e);
+                        stream_span.record("otel.status_code", "ERROR");
+                        stream_span.record("otel.status_message", error.to_string());
+                        tracker.stream_failed(&error);
+
+                        // Terminate a partially sent event so that the error is parsed on its own
+                        let separator = if boundary.at_event_end() { "" } else { "\n\n" };
+                        yield Ok(Bytes::from(format!("{}{}data: [DONE]\n\n", separator, error.sse_event())));
+                        break;
+                    }
+                };
+                chunks += 1;
+                boundary.push(&item);
+                stream_span.record("chunks", chunks);
+                tracker.push_chunk(&item);
+                yield Ok::<_, axum::Error>(item);
+            }
+        };
+
+        Body::from_stream(Scoped::new(RequestId(request_id), stream))
+    } else {
+        log::debug!("--->>> single response");
+        let body_bytes = match res.bytes().instrument(upstream_span).await {
+            Ok(body_bytes) => body_bytes,
+            Err(e) => {
+                log::error!("failed to read the upstream response: {}", e);
+                return ProxyError::from(e).into_response();
+            }
+        };
+        tracker.set_body(body_bytes.clone());
+        Body::from(body_bytes)
+    };
+
+    let mut axum_response = Response::new(body);
+    *axum_response.status_mut() = status;
+    *axum_response.headers_mut() = headers;
+
+    axum_response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at_event_end(chunks: &[&str]) -> bool {
+        let mut boundary = EventBoundary::default();
+        for chunk in chunks {
+            boundary.push(chunk.as_bytes());
+        }
+        boundary.at_event_end()
+    }
+
+    #[test]
+    fn finds_event_ends_across_chunks() {
+        assert!(at_event_end(&[]));
+        assert!(at_event_end(&["data: {}\n\n"]));
+        assert!(at_event_end(&["data: {}\r\n\r\n"]));
+        assert!(at_event_end(&["data: {}\n", "\n"]));
+        assert!(at_event_end(&["data: {}\r\n\r", "\n"]));
+        assert!(at_event_end(&["data: {}\r", "\n", "\r", "\n"]));
+
+        assert!(!at_event_end(&["data: {}\n"]));
+        assert!(!at_event_end(&["data: {}\r\n"]));
+        assert!(!at_event_end(&["data: {}\n\n", "data: {"]));
+        assert!(!at_event_end(&["data: {}\n", "\n", "d"]));
+    }
+}
@@ -0,0 +1,232 @@
+use std::{sync::Arc, time::Instant};
+use axum::{body::Bytes, http::StatusCode};
+use chrono::{DateTime, Utc};
+
+use crate::activity_log::{body_to_value, ActivityLogger, ActivityRecord, ActivityTimings};
+use crate::key_manager::fingerprint;
+use crate::metrics::Metrics;
+use crate::models::{
+    completion::StreamAccumulator,
+    config::ModelPricing,
+    usage::{requested_model, TokenUsage},
+    ClientIdentity, ProxyError,
+};
+use crate::state::State as ProxyState;
+use crate::storage::{UsageRecord, UsageRecorder};
+
+/// What has been captured of the upstream response so far
+enum CapturedResponse {
+    None,
+    Body(Bytes),
+    Stream(StreamAccumulator),
+}
+
+/// Follows one request through the proxy and emits its usage and activity records
+/// when dropped, so that streams cut short by the client are still accounted for
+pub struct ExchangeTracker {
+    request_id: String,
+    upstream_request_id: Option<String>,
+    timestamp: DateTime<Utc>,
+    started: Instant,
+    client: Option<ClientIdentity>,
+    upstream_key_hash: String,
+    model: Option<String>,
+    /// The `pricing` entry matching `model`, `other` or `unknown`
+    model_label: String,
+    pricing: Option<ModelPricing>,
+    request_body: Bytes,
+    status: StatusCode,
+    upstream_ms: Option<u64>,
+    first_chunk_ms: Option<u64>,
+    response: CapturedResponse,
+    error: Option<String>,
+    usage_recorder: Option<UsageRecorder>,
+    activity_logger: Option<ActivityLogger>,
+    metrics: Arc<Metrics>,
+}
+
+impl ExchangeTracker {
+    /// Starts tracking a request that will be sent upstream with `api_key`
+    pub fn new(
+        state: &ProxyState,
+        request_id: String,
+        started: Instant,
+        client: Option<ClientIdentity>,
+        api_key: &str,
+        request_body: Bytes,
+    ) -> Self {
+        let model = requested_model(&request_body);
+        // The model comes from the client, so only configured names become metric labels
+        let (model_label, pricing) = match model.as_deref() {
+            Some(model) => match state.config.read().unwrap().pricing_for(model) {
+                Some((name, pricing)) => (name.to_string(), Some(pricing)),
+                None => ("other".to_string(), None),
+            },
+            None => ("unknown".to_string(), None),
+        };
+
+        state.metrics.in_flight_requests.inc();
+
+        Self {
+            request_id,
+            upstream_request_id: None,
+            timestamp: Utc::now(),
+            started,
+            client,
+            upstream_key_hash: fingerprint(api_key),
+            model,
+            model_label,
+            pricing,
+            request_body,
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            upstream_ms: None,
+            first_chunk_ms: None,
+            response: CapturedResponse::None,
+            error: None,
+            usage_recorder: state.usage_recorder.clone(),
+            activity_logger: state.activity_logger.clone(),
+            metrics: state.metrics.clone(),
+        }
+    }
+
+    /// Records the arrival of the upstream response headers
+    pub fn upstream_responded(&mut self, status: StatusCode, is_stream: bool, upstream_request_id: Option<String>) {
+        self.status = status;
+        self.upstream_request_id = upstream_request_id;
+        self.upstream_ms = Some(self.elapsed_ms());
+        if is_stream {
+            self.response = CapturedResponse::Stream(StreamAccumulator::default());
+        }
+    }
+
+    /// Records a chunk of a streamed response
+    pub fn push_chunk(&mut self, chunk: &[u8]) {
+        if self.first_chunk_ms.is_none() {
+            self.first_chunk_ms = Some(self.elapsed_ms());
+        }
+        if let CapturedResponse::Stream(accumulator) = &mut self.response {
+            accumulator.push(chunk);
+        }
+    }
+
+    /// Records that the upstream stream failed before it was complete
+    pub fn stream_failed(&mut self, error: &ProxyError) {
+        self.error = Some(error.to_string());
+    }
+
+    /// Records the complete body of a non-streamed response
+    pub fn set_body(&mut self, body: Bytes) {
+        self.response = CapturedResponse::Body(body);
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    fn record_metrics(&self, total_ms: u64, usage: Option<&TokenUsage>) {
+        let metrics = &self.metrics;
+        let model = self.model_label.as_str();
+
+        metrics.in_flight_requests.dec();
+        metrics
+            .upstream_requests
+            .with_label_values(&[model, self.status.as_str(), self.upstream_key_hash.as_str()])
+            .inc();
+        metrics
+            .upstream_request_duration
+            .with_label_values(&[model])
+            .observe(total_ms as f64 / 1000.0);
+        if self.error.is_some() {
+            metrics.stream_errors.with_label_values(&[model]).inc();
+        }
+        if let Some(first_chunk_ms) = self.first_chunk_ms {
+            metrics
+                .time_to_first_token
+                .with_label_values(&[model])
+                .observe(first_chunk_ms as f64 / 1000.0);
+        }
+        if let Some(usage) = usage {
+            metrics.tokens.with_label_values(&[model, "prompt"]).inc_by(usage.prompt_tokens);
+            metrics.tokens.with_label_values(&[model, "completion"]).inc_by(usage.completion_tokens);
+            if let Some(pricing) = self.pricing {
+                metrics
+                    .cost
+                    .with_label_values(&[model])
+                    .inc_by(pricing.cost(usage.prompt_tokens, usage.completion_tokens));
+            }
+        }
+    }
+
+    fn usage(&self) -> Option<TokenUsage> {
+        match &self.response {
+            CapturedResponse::None => None,
+            CapturedResponse::Body(body) => TokenUsage::from_body(body),
+            CapturedResponse::Stream(accumulator) => accumulator.usage(),
+        }
+    }
+}
+
+impl Drop for ExchangeTracker {
+    fn drop(&mut self) {
+        let total_ms = self.elapsed_ms();
+        let usage = self.usage();
+        self.record_metrics(total_ms, usage.as_ref());
+
+        if self.usage_recorder.is_none() && self.activity_logger.is_none() {
+            return;
+        }
+
+        if let Some(recorder) = self.usage_recorder.take() {
+            let tokens = usage.unwrap_or_default();
+            recorder.record(UsageRecord {
+                timestamp: self.timestamp,
+                access_key: self.client.as_ref().map(|client| client.id.clone()),
+                upstream_key_hash: self.upstream_key_hash.clone(),
+                model: self.model.clone(),
+                status: self.status.as_u16(),
+                latency_ms: total_ms,
+                prompt_tokens: tokens.prompt_tokens,
+                completion_tokens: tokens.completion_tokens,
+                total_tokens: tokens.total_tokens,
+                cost: self
+                    .pricing
+                    .map(|pricing| pricing.cost(tokens.prompt_tokens, tokens.completion_tokens))
+                    .unwrap_or_default(),
+            });
+        }
+
+        if let Some(logger) = self.activity_logger.take() {
+            let (stream, response) = match &self.response {
+                CapturedResponse::None => (false, serde_json::Value::Null),
+                CapturedResponse::Body(body) => (false, body_to_value(body)),
+                CapturedResponse::Stream(accumulator) => (true, accumulator.to_completion()),
+            };
+
+            logger.log(ActivityRecord {
+                request_id: self.request_id.clone(),
+                upstream_request_id: self.upstream_request_id.clone(),
+                timestamp: self.timestamp,
+                client_key_id: self.client.as_ref().map(|client| client.id.clone()),
+                client_kind: self.client.as_ref().map(|client| client.kind),
+                client_owner: self.client.as_ref().and_then(|client| client.owner.clone()),
+                client_groups: self
+                    .client
+                    .as_ref()
+                    .map(|client| client.groups.clone())
+                    .unwrap_or_default(),
+                model: self.model.clone(),
+                status: self.status.as_u16(),
+                stream,
+                timings: ActivityTimings {
+                    upstream_ms: self.upstream_ms,
+                    first_chunk_ms: self.first_chunk_ms,
+                    total_ms,
+                },
+                usage,
+                request: body_to_value(&self.request_body),
+                response,
+                error: self.error.clone(),
+            });
+        }
+    }
+}
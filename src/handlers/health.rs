@@ -0,0 +1,65 @@
+use axum::{
+    extract::State,
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::key_manager::KeyPoolHealth;
+use crate::state::State as ProxyState;
+
+/// Body of the `/status` endpoint
+#[derive(Debug, Serialize)]
+pub struct StatusView {
+    pub version: String,
+    pub uptime_secs: u64,
+    /// Whether the proxy is shutting down and waiting for in-flight requests
+    pub draining: bool,
+    pub upstream_keys: KeyPoolHealth,
+}
+
+/// Liveness probe: answers as long as the process serves requests
+pub async fn healthz_handler() -> &'static str {
+    "ok"
+}
+
+/// Readiness probe: fails while shutting down and when no upstream key is usable
+pub async fn readyz_handler(State(state): State<ProxyState>) -> Response {
+    let reason = if state.draining.is_cancelled() {
+        Some("shutting down")
+    } else if !state.key_manager.read().unwrap().has_usable_key() {
+        Some("no usable upstream API key")
+    } else {
+        None
+    };
+
+    match reason {
+        None => Json(serde_json::json!({ "status": "ready" })).into_response(),
+        Some(reason) => {
+            let body = Json(serde_json::json!({
+                "status": "not_ready",
+                "reason": reason,
+            }));
+            (StatusCode::SERVICE_UNAVAILABLE, body).into_response()
+        }
+    }
+}
+
+pub async fn status_handler(State(state): State<ProxyState>) -> Json<StatusView> {
+    let version = state.config.read().unwrap().version.clone();
+
+    Json(StatusView {
+        version,
+        uptime_secs: state.started_at.elapsed().as_secs(),
+        draining: state.draining.is_cancelled(),
+        upstream_keys: state.key_manager.read().unwrap().health(),
+    })
+}
+
+/// Prometheus scrape endpoint
+pub async fn metrics_handler(State(state): State<ProxyState>) -> impl IntoResponse {
+    let keys = state.key_manager.read().unwrap().health();
+
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], state.metrics.render(&keys))
+}
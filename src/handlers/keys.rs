@@ -0,0 +1,364 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{
+        rejection::{JsonRejection, PathRejection},
+        Path, State,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::key_hashing::{generate_key, hash_key, is_hashed, verify_key};
+use crate::key_manager::fingerprint;
+use crate::key_persistence::persist_keys;
+use crate::models::config::{is_header_safe, parse_timestamp, AccessKey, Config};
+use crate::models::ProxyError;
+use crate::redaction::Redactor;
+use crate::secrets::{is_reference, resolve};
+use crate::state::State as ProxyState;
+
+/// Serializes key changes so that concurrent requests persist them in order
+pub(crate) static KEY_CHANGES: Mutex<()> = Mutex::const_new(());
+
+/// The key lists managed by the `/admin/keys/*` endpoints
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyList {
+    /// `api_keys`, used to authenticate with the upstream API
+    Upstream,
+    /// `access_keys`, used by clients to authenticate with the proxy
+    Access,
+}
+
+impl KeyList {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            KeyList::Upstream => "upstream",
+            KeyList::Access => "access",
+        }
+    }
+
+    fn keys(self, config: &Config) -> &[AccessKey] {
+        match self {
+            KeyList::Upstream => &config.api_keys,
+            KeyList::Access => &config.access_keys,
+        }
+    }
+
+    fn keys_mut(self, config: &mut Config) -> &mut Vec<AccessKey> {
+        match self {
+            KeyList::Upstream => &mut config.api_keys,
+            KeyList::Access => &mut config.access_keys,
+        }
+    }
+}
+
+/// A key as listed by the admin API, without the key itself
+#[derive(Debug, Serialize)]
+pub struct KeyView {
+    /// Fingerprint of the configured entry, used to address the key
+    pub id: String,
+    pub hashed: bool,
+    pub disabled: bool,
+    /// The `file:` or `env:` reference of the key
+    pub source: Option<String>,
+    pub not_before: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub description: Option<String>,
+    pub owner: Option<String>,
+}
+
+impl From<&AccessKey> for KeyView {
+    fn from(entry: &AccessKey) -> Self {
+        KeyView {
+            id: fingerprint(&entry.key),
+            hashed: is_hashed(&entry.key),
+            disabled: entry.disabled,
+            source: entry.source.clone(),
+            not_before: entry.not_before,
+            expires_at: entry.expires_at,
+            description: entry.description.clone(),
+            owner: entry.owner.clone(),
+        }
+    }
+}
+
+/// Body of `POST /admin/keys/{list}`
+///
+/// Access keys are generated when `key` is omitted and stored hashed unless `hash` is
+/// false; upstream keys must be given and are stored as is. `file:` and `env:`
+/// references are resolved and persisted as references.
+#[derive(Debug, Deserialize)]
+pub struct NewKey {
+    pub key: Option<String>,
+    pub hash: Option<bool>,
+    pub not_before: Option<String>,
+    pub expires_at: Option<String>,
+    pub description: Option<String>,
+    pub owner: Option<String>,
+}
+
+/// Errors of the key changing endpoints
+#[derive(Debug, thiserror::Error)]
+pub enum KeyChangeError {
+    #[error("No key with id {0}")]
+    NotFound(String),
+    #[error("The key is already configured")]
+    Conflict,
+    #[error("The change could not be persisted and was not applied: {0}")]
+    NotPersisted(String),
+    #[error("The change would make the configuration invalid: {0}")]
+    Invalid(String),
+}
+
+impl From<KeyChangeError> for ProxyError {
+    fn from(e: KeyChangeError) -> Self {
+        let message = e.to_string();
+        match e {
+            KeyChangeError::NotFound(_) => ProxyError::NotFound { code: "key_not_found", message },
+            KeyChangeError::Conflict => ProxyError::Conflict { code: "key_exists", message },
+            KeyChangeError::NotPersisted(_) => ProxyError::Internal { code: "key_change_not_persisted", message },
+            KeyChangeError::Invalid(_) => ProxyError::InvalidRequest { message, param: None },
+        }
+    }
+}
+
+impl IntoResponse for KeyChangeError {
+    fn into_response(self) -> Response {
+        ProxyError::from(self).into_response()
+    }
+}
+
+/// Applies `change` to a key list, persists the keys when `persist_key_changes` is set,
+/// then updates the configuration, the key manager and the activity log redaction
+async fn change_keys<T>(
+    state: &ProxyState,
+    list: KeyList,
+    change: impl FnOnce(&mut Vec<AccessKey>) -> Result<T, KeyChangeError>,
+) -> Result<T, KeyChangeError> {
+    // Also held by reloads, so the configuration cannot change until the swap below
+    let _guard = KEY_CHANGES.lock().await;
+
+    // The change is applied to a copy first so that nothing is written, or later
+    // reloaded, that the proxy would refuse to start with
+    let mut changed = state.config.read().unwrap().clone();
+    let result = change(list.keys_mut(&mut changed))?;
+
+    // Validation reads certificate files and persisting writes the configuration file
+    let checked = tokio::task::spawn_blocking(move || {
+        if let Err(errors) = changed.validate() {
+            let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            return Err(KeyChangeError::Invalid(errors.join("; ")));
+        }
+        // Runtime keys are masked in the activity log like configured ones
+        let redactor = Redactor::new(&changed).map_err(|e| KeyChangeError::Invalid(e.to_string()))?;
+        if changed.persist_key_changes {
+            if let Err(e) = persist_keys(&Config::file_path(), &changed.api_keys, &changed.access_keys) {
+                log::error!("failed to persist key changes: {}", e);
+                return Err(KeyChangeError::NotPersisted(e.to_string()));
+            }
+        }
+        Ok((changed, redactor))
+    })
+    .await
+    .map_err(|e| KeyChangeError::NotPersisted(e.to_string()))?;
+    let (mut changed, redactor) = checked?;
+
+    {
+        let mut config = state.config.write().unwrap();
+        if let KeyList::Upstream = list {
+            // Swapped under the config lock so no request sees new keys with the old config
+            state.key_manager.write().unwrap().set_keys(changed.api_keys.clone());
+        }
+        *list.keys_mut(&mut config) = std::mem::take(list.keys_mut(&mut changed));
+    }
+    if let Some(activity_logger) = &state.activity_logger {
+        activity_logger.set_redactor(Arc::new(redactor));
+    }
+
+    Ok(result)
+}
+
+pub async fn list_keys_handler(
+    State(state): State<ProxyState>,
+    path: Result<Path<KeyList>, PathRejection>,
+) -> Response {
+    let list = match path {
+        Ok(Path(list)) => list,
+        Err(e) => return ProxyError::from(e).into_response(),
+    };
+    let keys: Vec<KeyView> = {
+        let config = state.config.read().unwrap();
+        list.keys(&config).iter().map(KeyView::from).collect()
+    };
+
+    Json(serde_json::json!({ "data": keys })).into_response()
+}
+
+pub async fn add_key_handler(
+    State(state): State<ProxyState>,
+    path: Result<Path<KeyList>, PathRejection>,
+    new_key: Result<Json<NewKey>, JsonRejection>,
+) -> Response {
+    let list = match path {
+        Ok(Path(list)) => list,
+        Err(e) => return ProxyError::from(e).into_response(),
+    };
+    let new_key = match new_key {
+        Ok(Json(new_key)) => new_key,
+        Err(e) => return ProxyError::from(e).into_response(),
+    };
+    let parse = |name: &'static str, value: Option<String>| match value {
+        Some(value) => parse_timestamp(&value)
+            .map(Some)
+            .ok_or_else(|| ProxyError::invalid_param(name, format!("Invalid `{}` value: {}", name, value))),
+        None => Ok(None),
+    };
+    let (not_before, expires_at) = match (
+        parse("not_before", new_key.not_before),
+        parse("expires_at", new_key.expires_at),
+    ) {
+        (Ok(not_before), Ok(expires_at)) => (not_before, expires_at),
+        (Err(e), _) | (_, Err(e)) => return e.into_response(),
+    };
+    if let (Some(not_before), Some(expires_at)) = (not_before, expires_at) {
+        if not_before >= expires_at {
+            return ProxyError::invalid_param("expires_at", "`not_before` must be earlier than `expires_at`")
+                .into_response();
+        }
+    }
+
+    // A generated key is returned once and never stored in plaintext
+    let (key, generated) = match (list, new_key.key) {
+        (_, Some(key)) if key.trim().is_empty() => {
+            return ProxyError::invalid_param("key", "`key` must not be empty").into_response()
+        }
+        (_, Some(key)) => (key, false),
+        (KeyList::Access, None) => (generate_key(), true),
+        (KeyList::Upstream, None) => {
+            return ProxyError::invalid_param("key", "`key` is required").into_response()
+        }
+    };
+    // References are resolved now and kept so that the secret can change behind them
+    let (key, source) = if is_reference(&key) {
+        match resolve(&key) {
+            Ok(secret) => (secret, Some(key)),
+            Err(e) => return ProxyError::invalid_param("key", e.to_string()).into_response(),
+        }
+    } else {
+        (key, None)
+    };
+    if !is_header_safe(&key) {
+        return ProxyError::invalid_param("key", "`key` must be usable in an HTTP header").into_response();
+    }
+    let stored = match list {
+        KeyList::Access if source.is_none() && new_key.hash.unwrap_or(true) && !is_hashed(&key) => {
+            hash_key(&key)
+        }
+        _ => key.clone(),
+    };
+
+    let entry = AccessKey {
+        key: stored,
+        not_before,
+        expires_at,
+        description: new_key.description,
+        owner: new_key.owner,
+        disabled: false,
+        source,
+    };
+    let view = KeyView::from(&entry);
+
+    let added = change_keys(&state, list, |keys| {
+        if keys.iter().any(|existing| verify_key(&key, &existing.key) || existing.key == entry.key) {
+            return Err(KeyChangeError::Conflict);
+        }
+        keys.push(entry);
+        Ok(())
+    })
+    .await;
+    if let Err(e) = added {
+        return e.into_response();
+    }
+
+    log::info!("admin added {} key {} {}", list.as_str(), view.id, view.description.as_deref().unwrap_or(""));
+
+    let mut body = serde_json::json!({ "data": view });
+    if generated {
+        body["key"] = serde_json::Value::String(key);
+    }
+
+    (StatusCode::CREATED, Json(body)).into_response()
+}
+
+async fn set_disabled(state: ProxyState, list: KeyList, id: String, disabled: bool) -> Response {
+    let changed = change_keys(&state, list, |keys| {
+        let entry = keys
+            .iter_mut()
+            .find(|entry| fingerprint(&entry.key) == id)
+            .ok_or_else(|| KeyChangeError::NotFound(id.clone()))?;
+        entry.disabled = disabled;
+        Ok(KeyView::from(&*entry))
+    })
+    .await;
+
+    match changed {
+        Ok(view) => {
+            log::info!("admin {} {} key {}", if disabled { "disabled" } else { "enabled" }, list.as_str(), id);
+            Json(serde_json::json!({ "data": view })).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+pub async fn disable_key_handler(
+    State(state): State<ProxyState>,
+    path: Result<Path<(KeyList, String)>, PathRejection>,
+) -> Response {
+    match path {
+        Ok(Path((list, id))) => set_disabled(state, list, id, true).await,
+        Err(e) => ProxyError::from(e).into_response(),
+    }
+}
+
+pub async fn enable_key_handler(
+    State(state): State<ProxyState>,
+    path: Result<Path<(KeyList, String)>, PathRejection>,
+) -> Response {
+    match path {
+        Ok(Path((list, id))) => set_disabled(state, list, id, false).await,
+        Err(e) => ProxyError::from(e).into_response(),
+    }
+}
+
+pub async fn delete_key_handler(
+    State(state): State<ProxyState>,
+    path: Result<Path<(KeyList, String)>, PathRejection>,
+) -> Response {
+    let (list, id) = match path {
+        Ok(Path(path)) => path,
+        Err(e) => return ProxyError::from(e).into_response(),
+    };
+    let deleted = change_keys(&state, list, |keys| {
+        let index = keys
+            .iter()
+            .position(|entry| fingerprint(&entry.key) == id)
+            .ok_or_else(|| KeyChangeError::NotFound(id.clone()))?;
+        keys.remove(index);
+        Ok(())
+    })
+    .await;
+
+    match deleted {
+        Ok(()) => {
+            log::info!("admin deleted {} key {}", list.as_str(), id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
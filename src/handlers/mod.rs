@@ -0,0 +1,5 @@
+pub mod admin;
+pub mod chat;
+pub mod health;
+pub mod keys;
+mod exchange;
\ No newline at end of file
@@ -0,0 +1,365 @@
+//! JWT bearer authentication for the OpenAI Proxy Carousel
+//!
+//! Tokens issued by the identity provider are verified against its JWKS, loaded from a
+//! local file or fetched from a URL and cached. The signature, `exp`, `aud` and `iss`
+//! are checked, then `sub` and the groups claim are mapped onto a [`ClientIdentity`].
+
+use std::time::{Duration, Instant};
+
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, DecodingKey, Validation};
+use serde_json::Value;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::models::{config::JwtConfig, AuthorizationError, ClientIdentity, IdentityKind};
+
+/// Reload the JWKS this often unless configured otherwise
+const DEFAULT_REFRESH: Duration = Duration::from_secs(3600);
+/// Minimum time between reloads triggered by an unknown `kid`, and between retries
+/// after a failed reload
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+/// Limits on fetching `jwks_url`, so that a hanging provider cannot hang authentication
+const JWKS_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const JWKS_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct CachedJwks {
+    keys: JwkSet,
+    loaded_at: Option<Instant>,
+    /// The last reload, whether it succeeded or not
+    attempted_at: Option<Instant>,
+}
+
+/// Verifies JWT bearer tokens against the configured JWKS
+pub struct JwtVerifier {
+    config: JwtConfig,
+    client: reqwest::Client,
+    jwks: RwLock<CachedJwks>,
+    /// Held while the JWKS is reloaded, so that only one request reloads it
+    refresh: Mutex<()>,
+}
+
+/// Returns whether a bearer token has the shape of a JWT rather than an access key
+pub fn looks_like_jwt(token: &str) -> bool {
+    token.starts_with("eyJ") && token.split('.').count() == 3
+}
+
+impl JwtVerifier {
+    pub fn new(config: JwtConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::builder()
+                .connect_timeout(JWKS_CONNECT_TIMEOUT)
+                .timeout(JWKS_TIMEOUT)
+                .build()
+                .expect("the JWKS client is built without custom TLS settings"),
+            jwks: RwLock::new(CachedJwks {
+                keys: JwkSet { keys: Vec::new() },
+                loaded_at: None,
+                attempted_at: None,
+            }),
+            refresh: Mutex::new(()),
+        }
+    }
+
+    /// Verifies `token` and returns the identity it carries
+    pub async fn verify(&self, token: &str) -> Result<ClientIdentity, AuthorizationError> {
+        let header = decode_header(token).map_err(|e| {
+            log::warn!("rejected JWT: {}", e);
+            AuthorizationError::InvalidToken
+        })?;
+        if !self.config.algorithms.contains(&header.alg) {
+            log::warn!("rejected JWT: algorithm {:?} is not allowed", header.alg);
+            return Err(AuthorizationError::InvalidToken);
+        }
+
+        let key = self.decoding_key(header.kid.as_deref()).await.map_err(|e| {
+            log::warn!("rejected JWT: {}", e);
+            AuthorizationError::InvalidToken
+        })?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.config.audience]);
+        validation.set_issuer(&[&self.config.issuer]);
+        validation.set_required_spec_claims(&["exp", "aud", "iss", "sub"]);
+
+        let claims = decode::<Value>(token, &key, &validation)
+            .map_err(|e| {
+                log::warn!("rejected JWT: {}", e);
+                AuthorizationError::InvalidToken
+            })?
+            .claims;
+
+        let subject = claims
+            .get("sub")
+            .and_then(Value::as_str)
+            .ok_or(AuthorizationError::InvalidToken)?
+            .to_string();
+        let groups: Vec<String> = match claims.get(&self.config.groups_claim) {
+            Some(Value::Array(groups)) => groups
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect(),
+            Some(Value::String(group)) => vec![group.clone()],
+            _ => Vec::new(),
+        };
+
+        if !self.config.allowed_groups.is_empty()
+            && !groups.iter().any(|group| self.config.allowed_groups.contains(group))
+        {
+            log::warn!("rejected JWT for {}: none of its groups is allowed", subject);
+            return Err(AuthorizationError::Forbidden);
+        }
+
+        Ok(ClientIdentity {
+            id: subject,
+            kind: IdentityKind::Jwt,
+            groups,
+            owner: None,
+        })
+    }
+
+    /// Finds the key for `kid`, reloading the JWKS when it is stale or the key is unknown
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<DecodingKey, String> {
+        let refresh = self
+            .config
+            .jwks_refresh_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_REFRESH);
+
+        {
+            let jwks = self.jwks.read().await;
+            let fresh = jwks.loaded_at.is_some_and(|loaded_at| loaded_at.elapsed() < refresh);
+            if fresh {
+                if let Some(key) = Self::find_key(&jwks.keys, kid) {
+                    return key;
+                }
+            }
+        }
+
+        {
+            let _refresh = self.refresh.lock().await;
+            let recently_attempted = self
+                .jwks
+                .read()
+                .await
+                .attempted_at
+                .is_some_and(|attempted_at| attempted_at.elapsed() < MIN_REFRESH_INTERVAL);
+            if !recently_attempted {
+                // Loaded without holding the cache, which other requests keep reading meanwhile
+                let loaded = self.load().await;
+                let mut jwks = self.jwks.write().await;
+                jwks.attempted_at = Some(Instant::now());
+                match loaded {
+                    Ok(keys) => {
+                        jwks.keys = keys;
+                        jwks.loaded_at = jwks.attempted_at;
+                    }
+                    // Keep serving the previous keys if the provider is temporarily unavailable
+                    Err(e) => log::error!(
+                        "failed to load JWKS, retrying in {:?} at the earliest: {}",
+                        MIN_REFRESH_INTERVAL,
+                        e
+                    ),
+                }
+            }
+        }
+
+        let jwks = self.jwks.read().await;
+        Self::find_key(&jwks.keys, kid)
+            .unwrap_or_else(|| Err(format!("no JWKS key matches kid {:?}", kid)))
+    }
+
+    fn find_key(keys: &JwkSet, kid: Option<&str>) -> Option<Result<DecodingKey, String>> {
+        let jwk = match kid {
+            Some(kid) => keys.find(kid)?,
+            None if keys.keys.len() == 1 => &keys.keys[0],
+            None => return None,
+        };
+
+        Some(DecodingKey::from_jwk(jwk).map_err(|e| e.to_string()))
+    }
+
+    async fn load(&self) -> Result<JwkSet, String> {
+        if let Some(path) = &self.config.jwks_file {
+            let content = tokio::fs::read_to_string(path)
+                .await
+                .map_err(|e| format!("{}: {}", path, e))?;
+            return serde_json::from_str(&content).map_err(|e| format!("{}: {}", path, e));
+        }
+
+        let url = self
+            .config
+            .jwks_url
+            .as_deref()
+            .ok_or_else(|| "neither jwks_file nor jwks_url is configured".to_string())?;
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("{}: {}", url, e))?;
+
+        response.json().await.map_err(|e| format!("{}: {}", url, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+
+    const PRIVATE_KEY: &str = include_str!("../tests/fixtures/jwt-rsa.pem");
+    const PUBLIC_JWK: &str = include_str!("../tests/fixtures/jwt-rsa.jwk.json");
+
+    /// Writes a JWKS with the test key under each of `kids`
+    fn write_jwks(path: &Path, kids: &[&str]) {
+        let keys: Vec<Value> = kids
+            .iter()
+            .map(|kid| {
+                let mut jwk: Value = serde_json::from_str(PUBLIC_JWK).unwrap();
+                jwk["kid"] = json!(kid);
+                jwk
+            })
+            .collect();
+        std::fs::write(path, json!({ "keys": keys }).to_string()).unwrap();
+    }
+
+    fn verifier(jwks: &Path, allowed_groups: &[&str]) -> JwtVerifier {
+        JwtVerifier::new(JwtConfig {
+            jwks_file: Some(jwks.display().to_string()),
+            jwks_url: None,
+            jwks_refresh_secs: None,
+            issuer: "https://idp.test/".to_string(),
+            audience: "openai-proxy".to_string(),
+            algorithms: vec![Algorithm::RS256],
+            groups_claim: "groups".to_string(),
+            allowed_groups: allowed_groups.iter().map(|group| group.to_string()).collect(),
+        })
+    }
+
+    fn claims() -> Value {
+        json!({
+            "sub": "svc-batch",
+            "iss": "https://idp.test/",
+            "aud": "openai-proxy",
+            "exp": chrono::Utc::now().timestamp() + 600,
+            "groups": ["batch"],
+        })
+    }
+
+    fn sign(kid: &str, claims: &Value) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_string());
+        encode(&header, claims, &EncodingKey::from_rsa_pem(PRIVATE_KEY.as_bytes()).unwrap()).unwrap()
+    }
+
+    fn jwks_file(kids: &[&str]) -> (tempfile::TempDir, std::path::PathBuf) {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("jwks.json");
+        write_jwks(&path, kids);
+        (directory, path)
+    }
+
+    #[tokio::test]
+    async fn accepts_valid_token() {
+        let (_directory, jwks) = jwks_file(&["k1"]);
+        let identity = verifier(&jwks, &[]).verify(&sign("k1", &claims())).await.unwrap();
+
+        assert_eq!(identity.id, "svc-batch");
+        assert_eq!(identity.kind, IdentityKind::Jwt);
+        assert_eq!(identity.groups, vec!["batch".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_token() {
+        let (_directory, jwks) = jwks_file(&["k1"]);
+        let mut claims = claims();
+        claims["exp"] = json!(chrono::Utc::now().timestamp() - 3600);
+
+        let result = verifier(&jwks, &[]).verify(&sign("k1", &claims)).await;
+        assert!(matches!(result, Err(AuthorizationError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_audience_and_issuer() {
+        let (_directory, jwks) = jwks_file(&["k1"]);
+        let verifier = verifier(&jwks, &[]);
+
+        let mut wrong_audience = claims();
+        wrong_audience["aud"] = json!("another-service");
+        let result = verifier.verify(&sign("k1", &wrong_audience)).await;
+        assert!(matches!(result, Err(AuthorizationError::InvalidToken)));
+
+        let mut wrong_issuer = claims();
+        wrong_issuer["iss"] = json!("https://evil.test/");
+        let result = verifier.verify(&sign("k1", &wrong_issuer)).await;
+        assert!(matches!(result, Err(AuthorizationError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn rejects_disallowed_algorithm() {
+        let (_directory, jwks) = jwks_file(&["k1"]);
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("k1".to_string());
+        let token = encode(&header, &claims(), &EncodingKey::from_secret(PUBLIC_JWK.as_bytes())).unwrap();
+
+        let result = verifier(&jwks, &[]).verify(&token).await;
+        assert!(matches!(result, Err(AuthorizationError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn reloads_jwks_for_unknown_kid_at_most_every_refresh_interval() {
+        let (_directory, jwks) = jwks_file(&["k1"]);
+        let verifier = verifier(&jwks, &[]);
+        verifier.verify(&sign("k1", &claims())).await.unwrap();
+
+        // The provider rotates to a new key right after the JWKS was loaded
+        write_jwks(&jwks, &["k1", "k2"]);
+        let result = verifier.verify(&sign("k2", &claims())).await;
+        assert!(matches!(result, Err(AuthorizationError::InvalidToken)));
+
+        verifier.jwks.write().await.attempted_at = Some(Instant::now() - MIN_REFRESH_INTERVAL);
+        let identity = verifier.verify(&sign("k2", &claims())).await.unwrap();
+        assert_eq!(identity.id, "svc-batch");
+
+        let result = verifier.verify(&sign("k3", &claims())).await;
+        assert!(matches!(result, Err(AuthorizationError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn backs_off_after_a_failed_reload() {
+        let directory = tempfile::tempdir().unwrap();
+        let jwks = directory.path().join("jwks.json");
+        let verifier = verifier(&jwks, &[]);
+
+        let result = verifier.verify(&sign("k1", &claims())).await;
+        assert!(matches!(result, Err(AuthorizationError::InvalidToken)));
+        assert!(verifier.jwks.read().await.attempted_at.is_some());
+
+        // The JWKS becomes available, but is only fetched once the back-off has passed
+        write_jwks(&jwks, &["k1"]);
+        let result = verifier.verify(&sign("k1", &claims())).await;
+        assert!(matches!(result, Err(AuthorizationError::InvalidToken)));
+
+        verifier.jwks.write().await.attempted_at = Some(Instant::now() - MIN_REFRESH_INTERVAL);
+        assert!(verifier.verify(&sign("k1", &claims())).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_token_without_allowed_group() {
+        let (_directory, jwks) = jwks_file(&["k1"]);
+        let verifier = verifier(&jwks, &["research"]);
+
+        let result = verifier.verify(&sign("k1", &claims())).await;
+        assert!(matches!(result, Err(AuthorizationError::Forbidden)));
+
+        let mut claims = claims();
+        claims["groups"] = json!("research");
+        assert!(verifier.verify(&sign("k1", &claims)).await.is_ok());
+    }
+}
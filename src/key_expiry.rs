@@ -0,0 +1,58 @@
+//! Expiry warnings for access keys
+//!
+//! A background task checks the configured API, access and admin keys once a day and logs
+//! a warning for every key that expires within `auth.expiry_warning_days`, so keys
+//! can be rotated before clients start getting `access_key_expired` errors.
+
+use std::time::Duration;
+
+use chrono::Utc;
+
+use crate::models::config::{AccessKey, Config};
+use crate::state::State as ProxyState;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn warn_expiring(list: &str, keys: &[AccessKey], warning_days: u32) {
+    let now = Utc::now();
+    let horizon = now + chrono::Duration::days(i64::from(warning_days));
+
+    for (index, entry) in keys.iter().enumerate() {
+        let Some(expires_at) = entry.expires_at.filter(|_| !entry.disabled) else {
+            continue;
+        };
+
+        if expires_at <= now {
+            log::warn!("{}[{}] {} expired at {}", list, index, entry.label(), expires_at);
+        } else if expires_at <= horizon {
+            log::warn!(
+                "{}[{}] {} expires at {} (in {} days), rotate it",
+                list,
+                index,
+                entry.label(),
+                expires_at,
+                (expires_at - now).num_days()
+            );
+        }
+    }
+}
+
+/// Logs a warning for every configured key that is expired or about to expire
+pub fn warn_expiring_keys(config: &Config) {
+    let warning_days = config.auth.expiry_warning_days;
+    warn_expiring("api_keys", &config.api_keys, warning_days);
+    warn_expiring("access_keys", &config.access_keys, warning_days);
+    warn_expiring("admin_keys", &config.admin_keys, warning_days);
+}
+
+/// Starts the daily expiry check, reading the keys from the current configuration
+pub fn spawn_expiry_monitor(state: ProxyState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let config = state.config.read().unwrap().clone();
+            warn_expiring_keys(&config);
+        }
+    });
+}
@@ -0,0 +1,155 @@
+//! Hashed access keys for the OpenAI Proxy Carousel
+//!
+//! Entries of `access_keys` and `admin_keys` may be stored as
+//! `sha256:<salt>:<digest>` instead of plaintext, where the digest is the SHA-256 of
+//! the salt, an optional server-side pepper and the key. Access keys are long random
+//! tokens, so a fast salted hash is sufficient; the pepper, read from the
+//! `ACCESS_KEY_PEPPER` environment variable, keeps a leaked config from being enough
+//! to brute-force short keys. All comparisons are constant time.
+
+use std::env;
+
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// Environment variable holding the pepper mixed into every hashed key
+pub const PEPPER_ENV: &str = "ACCESS_KEY_PEPPER";
+
+const SHA256_PREFIX: &str = "sha256:";
+
+fn pepper() -> Vec<u8> {
+    env::var(PEPPER_ENV).map(String::into_bytes).unwrap_or_default()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+fn digest(salt: &[u8], pepper: &[u8], key: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(pepper);
+    hasher.update(key.as_bytes());
+    hasher.finalize().into()
+}
+
+/// Returns whether a configured entry is a hash rather than a plaintext key
+pub fn is_hashed(entry: &str) -> bool {
+    entry.starts_with(SHA256_PREFIX)
+}
+
+/// Hashes `key` with a fresh random salt and the configured pepper
+pub fn hash_key(key: &str) -> String {
+    hash_key_with(key, &pepper())
+}
+
+fn hash_key_with(key: &str, pepper: &[u8]) -> String {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+
+    format!(
+        "{}{}:{}",
+        SHA256_PREFIX,
+        to_hex(&salt),
+        to_hex(&digest(&salt, pepper, key))
+    )
+}
+
+/// Generates a new random access key
+pub fn generate_key() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    format!("pk-{}", to_hex(&bytes))
+}
+
+/// Checks a presented key against a configured entry, hashed or plaintext, in constant time
+pub fn verify_key(presented: &str, entry: &str) -> bool {
+    verify_key_with(presented, entry, &pepper())
+}
+
+fn verify_key_with(presented: &str, entry: &str, pepper: &[u8]) -> bool {
+    match entry.strip_prefix(SHA256_PREFIX) {
+        Some(hashed) => {
+            let Some((salt, expected)) = hashed.split_once(':') else {
+                return false;
+            };
+            let (Some(salt), Some(expected)) = (from_hex(salt), from_hex(expected)) else {
+                return false;
+            };
+
+            digest(&salt, pepper, presented).ct_eq(expected.as_slice()).into()
+        }
+        // Compare digests so that the comparison does not depend on the key lengths
+        None => {
+            let presented: [u8; 32] = Sha256::digest(presented.as_bytes()).into();
+            let entry: [u8; 32] = Sha256::digest(entry.as_bytes()).into();
+            presented.ct_eq(&entry).into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_hashed_key_without_pepper() {
+        let entry = hash_key_with("pk-secret", b"");
+
+        assert!(is_hashed(&entry));
+        assert!(verify_key_with("pk-secret", &entry, b""));
+        assert!(!verify_key_with("pk-other", &entry, b""));
+    }
+
+    #[test]
+    fn verifies_hashed_key_with_pepper() {
+        let entry = hash_key_with("pk-secret", b"pepper");
+
+        assert!(verify_key_with("pk-secret", &entry, b"pepper"));
+        assert!(!verify_key_with("pk-other", &entry, b"pepper"));
+        assert!(!verify_key_with("pk-secret", &entry, b"another pepper"));
+        assert!(!verify_key_with("pk-secret", &entry, b""));
+    }
+
+    #[test]
+    fn salts_every_hash() {
+        assert_ne!(hash_key_with("pk-secret", b""), hash_key_with("pk-secret", b""));
+    }
+
+    #[test]
+    fn rejects_malformed_hashes() {
+        let entry = hash_key_with("pk-secret", b"");
+        let (salt, digest) = entry.trim_start_matches(SHA256_PREFIX).split_once(':').unwrap();
+
+        for malformed in [
+            "sha256:".to_string(),
+            format!("sha256:{salt}"),
+            format!("sha256:{salt}:"),
+            format!("sha256:{salt}:{}", &digest[1..]),
+            format!("sha256:zz{}:{digest}", &salt[2..]),
+            format!("sha256:{salt}:{digest}:extra"),
+        ] {
+            assert!(!verify_key_with("pk-secret", &malformed, b""), "{malformed}");
+        }
+    }
+
+    #[test]
+    fn verifies_plaintext_entries() {
+        assert!(verify_key_with("pk-secret", "pk-secret", b"pepper"));
+        assert!(!verify_key_with("pk-secret", "pk-secret-2", b"pepper"));
+        assert!(!verify_key_with("", "pk-secret", b""));
+    }
+}
@@ -1,30 +1,91 @@
+use chrono::Utc;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::models::config::AccessKey;
+
+/// Counts of the upstream keys by state, reported by `/status`
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyPoolHealth {
+    pub total: usize,
+    pub usable: usize,
+    pub disabled: usize,
+    pub expired: usize,
+    /// Keys whose `not_before` is still in the future
+    pub pending: usize,
+    /// Fingerprint of the key requests are currently sent with
+    pub current: Option<String>,
+}
+
 pub struct KeyManager {
-    keys: Vec<String>,
+    keys: Vec<AccessKey>,
     current_index: usize,
 }
 
 impl KeyManager {
-    pub fn new(api_keys: Vec<String>) -> Self {
+    pub fn new(api_keys: Vec<AccessKey>) -> Self {
         Self {
             keys: api_keys,
             current_index: 0,
         }
     }
 
-    pub fn get_key(&mut self) -> String {
-        let len = self.keys.len(); 
+    /// Returns the current key, skipping disabled keys and keys outside their validity
+    /// window, or `None` when no key is usable
+    pub fn get_key(&mut self) -> Option<String> {
+        let len = self.keys.len();
+        let now = Utc::now();
 
-        if  self.current_index >= len {
-            self.current_index = 0;
+        for offset in 0..len {
+            let index = (self.current_index + offset) % len;
+            if self.keys[index].is_usable(now) {
+                self.current_index = index;
+                return Some(self.keys[index].key.clone());
+            }
         }
 
-        let key = self.keys[self.current_index].clone();
-        
-        key
+        None
+    }
+
+    /// Returns whether at least one key is usable right now
+    pub fn has_usable_key(&self) -> bool {
+        let now = Utc::now();
+        self.keys.iter().any(|key| key.is_usable(now))
+    }
+
+    pub fn health(&self) -> KeyPoolHealth {
+        let now = Utc::now();
+        let count = |predicate: &dyn Fn(&AccessKey) -> bool| self.keys.iter().filter(|key| predicate(key)).count();
+
+        KeyPoolHealth {
+            total: self.keys.len(),
+            usable: count(&|key| key.is_usable(now)),
+            disabled: count(&|key| key.disabled),
+            expired: count(&|key| key.expires_at.is_some_and(|expires_at| now >= expires_at)),
+            pending: count(&|key| key.not_before.is_some_and(|not_before| now < not_before)),
+            current: self
+                .keys
+                .get(self.current_index % self.keys.len().max(1))
+                .filter(|key| key.is_usable(now))
+                .map(|key| fingerprint(&key.key)),
+        }
     }
 
     pub fn switch_key(&mut self) {
-        self.current_index += 1;        
+        self.current_index += 1;
+    }
+
+    /// Replaces the keys, e.g. after they were changed through the admin API
+    pub fn set_keys(&mut self, api_keys: Vec<AccessKey>) {
+        self.keys = api_keys;
+        if self.current_index >= self.keys.len() {
+            self.current_index = 0;
+        }
     }
 }
 
+/// Returns a short, non-reversible identifier for a secret key, safe to store and log
+pub fn fingerprint(key: &str) -> String {
+    let digest = Sha256::digest(key.as_bytes());
+    digest[..8].iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,188 @@
+//! Persistence of key changes for the OpenAI Proxy Carousel
+//!
+//! Changes made through the admin API are written back to the `api_keys` and
+//! `access_keys` arrays of the TOML configuration file. The rest of the file,
+//! including comments and formatting, is left untouched, and the file is replaced
+//! atomically so a crash never leaves it half written.
+
+use std::{fs, path::Path};
+
+use chrono::SecondsFormat;
+use toml_edit::{Array, DocumentMut, InlineTable, Item, Value};
+
+use crate::models::config::AccessKey;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PersistError {
+    #[error("{0}: only TOML configuration files can be updated")]
+    Unsupported(String),
+    #[error("{path}: {source}")]
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("{path}: {source}")]
+    Parse {
+        path: String,
+        source: toml_edit::TomlError,
+    },
+}
+
+/// Converts an entry to a string, or to an inline table when it carries metadata
+fn entry_to_value(entry: &AccessKey) -> Value {
+    // Keys resolved from a reference are written back as the reference
+    let key = entry.source.as_deref().unwrap_or(&entry.key);
+    if entry.is_plain() {
+        return Value::from(key);
+    }
+
+    let mut table = InlineTable::new();
+    table.insert("key", Value::from(key));
+    if let Some(not_before) = entry.not_before {
+        table.insert("not_before", Value::from(not_before.to_rfc3339_opts(SecondsFormat::Secs, true)));
+    }
+    if let Some(expires_at) = entry.expires_at {
+        table.insert("expires_at", Value::from(expires_at.to_rfc3339_opts(SecondsFormat::Secs, true)));
+    }
+    if let Some(description) = &entry.description {
+        table.insert("description", Value::from(description.as_str()));
+    }
+    if let Some(owner) = &entry.owner {
+        table.insert("owner", Value::from(owner.as_str()));
+    }
+    if entry.disabled {
+        table.insert("disabled", Value::from(true));
+    }
+
+    Value::InlineTable(table)
+}
+
+/// Formats the entries as a multi-line array, one entry per line
+fn entries_to_item(entries: &[AccessKey]) -> Item {
+    let mut array = Array::new();
+    for entry in entries {
+        array.push_formatted(entry_to_value(entry).decorated("\n  ", ""));
+    }
+    array.set_trailing_comma(!entries.is_empty());
+    array.set_trailing(if entries.is_empty() { "" } else { "\n" });
+
+    Item::Value(Value::Array(array))
+}
+
+/// Writes `api_keys` and `access_keys` to the configuration file at `path`
+pub fn persist_keys(
+    path: &Path,
+    api_keys: &[AccessKey],
+    access_keys: &[AccessKey],
+) -> Result<(), PersistError> {
+    let display = path.display().to_string();
+    if path.extension().is_some_and(|extension| extension == "json") {
+        return Err(PersistError::Unsupported(display));
+    }
+    let io_error = |source| PersistError::Io { path: display.clone(), source };
+
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(io_error(e)),
+    };
+    let mut document: DocumentMut = content.parse().map_err(|source| PersistError::Parse {
+        path: display.clone(),
+        source,
+    })?;
+
+    document["api_keys"] = entries_to_item(api_keys);
+    document["access_keys"] = entries_to_item(access_keys);
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, document.to_string()).map_err(io_error)?;
+    fs::rename(&temporary, path).map_err(io_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+
+    const CONFIG: &str = r#"# Proxy configuration
+version = "0.1.0"
+port = 8080    # behind the load balancer
+
+# Upstream keys, rotated monthly
+api_keys = ["file:/run/secrets/openai", "env:OPENAI_KEY"]
+access_keys = ["enc:v1:c2VjcmV0"]
+
+[pricing."gpt-4o"]
+prompt = 2.5       # USD per million tokens
+completion = 10.0
+"#;
+
+    /// An entry resolved from `source`, as the configuration loader produces it
+    fn resolved(key: &str, source: Option<&str>) -> AccessKey {
+        AccessKey {
+            key: key.to_string(),
+            not_before: None,
+            expires_at: None,
+            description: None,
+            owner: None,
+            disabled: false,
+            source: source.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn rewrites_key_lists_only() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("config.toml");
+        fs::write(&path, CONFIG).unwrap();
+
+        let mut api_keys = vec![
+            resolved("sk-from-file", Some("file:/run/secrets/openai")),
+            resolved("sk-from-env", Some("env:OPENAI_KEY")),
+        ];
+        let mut access_keys = vec![resolved("ak-decrypted", Some("enc:v1:c2VjcmV0"))];
+        api_keys.push(resolved("sk-added", None));
+        access_keys.push(AccessKey {
+            expires_at: Some(Utc.with_ymd_and_hms(2027, 1, 1, 0, 0, 0).unwrap()),
+            owner: Some("batch".to_string()),
+            ..resolved("sha256:abc", None)
+        });
+        persist_keys(&path, &api_keys, &access_keys).unwrap();
+
+        let expected = CONFIG
+            .replace(
+                r#"api_keys = ["file:/run/secrets/openai", "env:OPENAI_KEY"]"#,
+                "api_keys = [\n  \"file:/run/secrets/openai\",\n  \"env:OPENAI_KEY\",\n  \"sk-added\",\n]",
+            )
+            .replace(
+                r#"access_keys = ["enc:v1:c2VjcmV0"]"#,
+                "access_keys = [\n  \"enc:v1:c2VjcmV0\",\n  \
+                 { key = \"sha256:abc\", expires_at = \"2027-01-01T00:00:00Z\", owner = \"batch\" },\n]",
+            );
+        assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+
+        // Removing the added keys again leaves the references in place
+        persist_keys(&path, &api_keys[..2], &access_keys[..1]).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# Proxy configuration\n"));
+        assert!(content.contains("api_keys = [\n  \"file:/run/secrets/openai\",\n  \"env:OPENAI_KEY\",\n]"));
+        assert!(content.contains("access_keys = [\n  \"enc:v1:c2VjcmV0\",\n]"));
+        assert!(content.contains("prompt = 2.5       # USD per million tokens\n"));
+        assert!(!content.contains("sk-from-file") && !content.contains("ak-decrypted"));
+
+        let files: Vec<_> = fs::read_dir(directory.path()).unwrap().collect();
+        assert_eq!(files.len(), 1, "the temporary file is renamed into place");
+    }
+
+    #[test]
+    fn refuses_json_configuration() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("config.json");
+
+        let result = persist_keys(&path, &[resolved("sk-a", None)], &[]);
+        assert!(matches!(result, Err(PersistError::Unsupported(_))));
+        assert!(!path.exists());
+    }
+}
@@ -1,40 +1,236 @@
-use std::sync::Once;
+//! Logging for the OpenAI Proxy Carousel
+//!
+//! `log` records and `tracing` events are written by one `tracing` subscriber, as text
+//! or JSON lines, to stdout and the log file configured in `[logging]`. Lines written
+//! while a request is handled carry its request ID. The levels can be changed at
+//! runtime when the configuration is reloaded; the outputs are set up once at startup.
+
+use std::{
+    fmt,
+    path::Path,
+    sync::OnceLock,
+};
+
 use log::LevelFilter;
-use log4rs::{
-    append::{
-        console::ConsoleAppender,
-        file::FileAppender
-    }, 
-    config::{Appender, Config as lofConfig, Root}, 
-    encode::pattern::PatternEncoder, 
-    filter::threshold::ThresholdFilter,
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use serde_json::{Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
 };
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::{
+    filter::{Directive, EnvFilter, LevelFilter as TracingLevelFilter},
+    fmt::{
+        format::Writer,
+        writer::{BoxMakeWriter, MakeWriterExt},
+        FmtContext, FormatEvent, FormatFields,
+    },
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    reload, Layer, Registry,
+};
+
+use crate::middleware::request_id;
+use crate::models::config::{Config, LogFormat, LogRotation, LoggingConfig};
+use crate::telemetry::{self, TelemetryError};
+
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+#[derive(Debug, thiserror::Error)]
+pub enum LoggerError {
+    #[error("logging: {0}")]
+    Filter(String),
+    #[error("failed to open the log file {path}: {message}")]
+    File { path: String, message: String },
+    #[error(transparent)]
+    Telemetry(#[from] TelemetryError),
+    #[error("failed to install the tracing subscriber: {0}")]
+    Subscriber(#[from] tracing::subscriber::SetGlobalDefaultError),
+    #[error("failed to install the log bridge: {0}")]
+    Log(#[from] log::SetLoggerError),
+}
+
+fn tracing_level(level: LevelFilter) -> TracingLevelFilter {
+    match level {
+        LevelFilter::Off => TracingLevelFilter::OFF,
+        LevelFilter::Error => TracingLevelFilter::ERROR,
+        LevelFilter::Warn => TracingLevelFilter::WARN,
+        LevelFilter::Info => TracingLevelFilter::INFO,
+        LevelFilter::Debug => TracingLevelFilter::DEBUG,
+        LevelFilter::Trace => TracingLevelFilter::TRACE,
+    }
+}
+
+/// Builds the filter from `log_level` (`Error` when unset) and the per-module levels
+pub fn build_filter(level: Option<LevelFilter>, logging: &LoggingConfig) -> Result<EnvFilter, String> {
+    let mut filter = EnvFilter::default().add_directive(tracing_level(level.unwrap_or(LevelFilter::Error)).into());
+    for (module, level) in &logging.levels {
+        let directive = format!("{}={}", module, tracing_level(*level))
+            .parse::<Directive>()
+            .map_err(|e| format!("invalid module `{}` in levels: {}", module, e))?;
+        filter = filter.add_directive(directive);
+    }
+
+    Ok(filter)
+}
+
+/// Writes to stdout and, unless `stdout_only` is set, to the log file
+fn build_writer(logging: &LoggingConfig) -> Result<BoxMakeWriter, LoggerError> {
+    let Some(path) = logging.file() else {
+        return Ok(BoxMakeWriter::new(std::io::stdout));
+    };
+
+    let file_error = |message: String| LoggerError::File { path: path.to_string(), message };
+    let path = Path::new(path);
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| file_error("not a file name".to_string()))?
+        .to_string_lossy();
+    let rotation = match logging.rotation {
+        LogRotation::Never => Rotation::NEVER,
+        LogRotation::Minutely => Rotation::MINUTELY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+    };
+
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(file_name.as_ref());
+    if let Some(max_files) = logging.max_files {
+        builder = builder.max_log_files(max_files);
+    }
+    let appender = builder.build(directory).map_err(|e| file_error(e.to_string()))?;
+
+    Ok(BoxMakeWriter::new(std::io::stdout.and(appender)))
+}
+
+/// Formats log lines as `<time> <level> <module> - [<request id>] <message>` or as JSON
+struct LineFormat(LogFormat);
+
+impl<S, N> FormatEvent<S, N> for LineFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        // Records from the `log` crate carry their real target in their fields
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        let request_id = request_id::current();
 
-use crate::models::config::Config as cfg;
-
-static INIT: Once = Once::new();
-
-pub fn configure_logger() {
-    INIT.call_once(|| {
-        let stdout = ConsoleAppender::builder()
-            .encoder(Box::new(PatternEncoder::new("{d(%Y-%m-%d %H:%M:%S)} {l} {t} - {m}{n}")))
-            .build();
-        let logfile = FileAppender::builder()
-            .encoder(Box::new(PatternEncoder::new("{d(%Y-%m-%d %H:%M:%S)} {l} {t} - {m}{n}")))
-            .build("log/data.log")
-            .unwrap();
-        let config = lofConfig::builder()
-            .appender(Appender::builder()
-                .filter(Box::new(ThresholdFilter::new(LevelFilter::Info)))
-                .build("stdout", Box::new(stdout))
-            );
-        let config = config.appender(Appender::builder()
-             .filter(Box::new(ThresholdFilter::new(cfg::instance().log_level.unwrap_or(LevelFilter::Error))))
-             .build("logfile", Box::new(logfile))
-            );
-        let rb = Root::builder().appender("stdout");
-        let rb = rb.appender("logfile");
-        let config = config.build(rb.build(cfg::instance().log_level.unwrap_or(LevelFilter::Error))).unwrap();
-        _ = log4rs::init_config(config).unwrap();
+        match self.0 {
+            LogFormat::Text => {
+                write!(
+                    writer,
+                    "{} {} {} - ",
+                    chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                    metadata.level(),
+                    metadata.target()
+                )?;
+                if let Some(request_id) = request_id {
+                    write!(writer, "[{}] ", request_id)?;
+                }
+                ctx.field_format().format_fields(writer.by_ref(), event)?;
+                writeln!(writer)
+            }
+            LogFormat::Json => {
+                let mut line = Map::new();
+                line.insert("timestamp".to_string(), Value::String(chrono::Utc::now().to_rfc3339()));
+                line.insert("level".to_string(), Value::String(metadata.level().to_string()));
+                line.insert("target".to_string(), Value::String(metadata.target().to_string()));
+                if let Some(request_id) = request_id {
+                    line.insert("request_id".to_string(), Value::String(request_id));
+                }
+                event.record(&mut JsonFields(&mut line));
+
+                writeln!(writer, "{}", Value::Object(line))
+            }
+        }
+    }
+}
+
+/// Collects the fields of an event into a JSON object
+struct JsonFields<'a>(&'a mut Map<String, Value>);
+
+impl JsonFields<'_> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        // `log.target`, `log.file`, ... are already covered by the metadata
+        if !field.name().starts_with("log.") {
+            self.0.insert(field.name().to_string(), value);
+        }
+    }
+}
+
+impl Visit for JsonFields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, Value::String(value.to_string()));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, Value::String(format!("{:?}", value)));
+    }
+}
+
+/// Installs the subscriber that writes the logs and, with `[telemetry]`, exports spans
+///
+/// The returned provider must be shut down before exiting so that buffered spans are sent.
+pub fn init(config: &Config) -> Result<Option<SdkTracerProvider>, LoggerError> {
+    let filter = build_filter(config.log_level, &config.logging).map_err(LoggerError::Filter)?;
+    let (filter, handle) = reload::Layer::new(filter);
+    let output = tracing_subscriber::fmt::layer()
+        .event_format(LineFormat(config.logging.format))
+        .with_writer(build_writer(&config.logging)?)
+        .with_filter(filter);
+
+    let provider = config.telemetry.as_ref().map(telemetry::build_provider).transpose()?;
+    // Spans are exported regardless of the log level, but only notable events are attached to them
+    let spans = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer()
+            .with_tracer(telemetry::tracer(provider))
+            .with_filter(TracingLevelFilter::INFO)
     });
+
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(output).with(spans))?;
+    tracing_log::LogTracer::init()?;
+    _ = FILTER.set(handle);
+
+    Ok(provider)
+}
+
+/// Applies new levels, e.g. after the configuration was reloaded
+pub fn set_levels(level: Option<LevelFilter>, logging: &LoggingConfig) {
+    let Some(handle) = FILTER.get() else {
+        return;
+    };
+
+    match build_filter(level, logging) {
+        Ok(filter) => {
+            if let Err(e) = handle.reload(filter) {
+                log::error!("failed to apply the new log levels: {}", e);
+            }
+        }
+        Err(e) => log::error!("failed to apply the new log levels: {}", e),
+    }
 }
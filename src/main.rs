@@ -1,173 +1,558 @@
 use std::sync::Arc;
 use axum::{
-    middleware::{Next, from_fn_with_state},
+    Extension,
+    extract::OriginalUri,
+    middleware::from_fn_with_state,
     Router,
-    body::{Body, Bytes},
-    extract::State,
-    http::{Request, HeaderMap, StatusCode, Uri, header::AUTHORIZATION},
-    response::{IntoResponse, Response},
-    routing::post,
+    http::{Method, Uri},
+    routing::{delete, get, post},
 };
-use reqwest::Client;
-use futures_util::stream::StreamExt;
-use http_body_util::BodyExt;
 
+mod cli;
 mod models;
 mod logger;
+mod metrics;
+mod activity_log;
+mod config_reload;
+mod redaction;
+mod peer;
+mod tls;
+mod telemetry;
+mod secrets;
+mod shutdown;
+mod key_hashing;
+mod jwt;
+mod key_expiry;
 mod key_manager;
+mod key_persistence;
 mod state;
 mod middleware;
+mod handlers;
+mod storage;
 
-use models::config::Config as cfg;
+use clap::Parser;
+use cli::{Cli, Command};
+use models::config::{Config as cfg, ListenerConfig};
+use models::ProxyError;
+use peer::PeerInfo;
 use state::State as ProxyState;
 use key_manager::KeyManager;
 use middleware::authorization;
+use storage::{SqliteUsageStore, UsageStore};
+use activity_log::ActivityLogger;
+use redaction::Redactor;
 
-async fn fallback(uri: Uri) -> (StatusCode, String) {
-    log::error!("fallback url: {}", uri);
-    (StatusCode::NOT_FOUND, format!("No route for {}", uri))
+async fn fallback(uri: Uri) -> ProxyError {
+    // Only the path is logged, the query string may carry a credential
+    log::error!("fallback url: {}", uri.path());
+    ProxyError::UnknownUrl(uri.path().to_string())
 }
 
-#[tokio::main]
-async fn main() {
-    let config = cfg::instance();
-    logger::configure_logger();
-    let address = format!("{}:{}", config.host, config.port);
-    let km = KeyManager::new(config.api_keys.clone());
+async fn method_not_allowed_fallback(method: Method, OriginalUri(uri): OriginalUri) -> ProxyError {
+    // The original URI keeps the `/admin` prefix that nesting strips
+    ProxyError::MethodNotAllowed { method: method.to_string(), path: uri.path().to_string() }
+}
+
+/// Prints a `sha256:` entry for `access_keys`, generating a new key when none is given
+/// (`-` reads the key from stdin so it does not end up in the shell history)
+fn hash_key_command(key: Option<String>) {
+    let key = match key.as_deref() {
+        Some("-") => {
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line).expect("failed to read key from stdin");
+            line.trim().to_string()
+        }
+        Some(key) => key.to_string(),
+        None => {
+            let key = key_hashing::generate_key();
+            println!("key:   {key}");
+            key
+        }
+    };
+
+    println!("entry: {}", key_hashing::hash_key(&key));
+}
+
+/// Prints the `enc:v1:` value of a secret, read from stdin unless given as an argument
+fn encrypt_secret_command(secret: Option<String>) {
+    let secret = match secret.as_deref() {
+        Some("-") | None => {
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line).expect("failed to read secret from stdin");
+            line.trim().to_string()
+        }
+        Some(secret) => secret.to_string(),
+    };
 
-    let state =  ProxyState::new(config.clone(), km).await;
-    let app = Router::new()
-    .route("/chat/completions", post(chat_completions_handler))
+    match secrets::encrypt(&secret) {
+        Ok(encrypted) => println!("{encrypted}"),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Builds the routes served on `listener`
+fn build_router(listener: &Arc<ListenerConfig>, state: ProxyState) -> Router {
+    let admin = Router::new()
+    .route("/usage/keys", get(handlers::admin::usage_by_key_handler))
+    .route("/usage/models", get(handlers::admin::usage_by_model_handler))
+    .route("/usage/days", get(handlers::admin::usage_by_day_handler))
+    .route("/keys/{list}", get(handlers::keys::list_keys_handler).post(handlers::keys::add_key_handler))
+    .route("/keys/{list}/{id}", delete(handlers::keys::delete_key_handler))
+    .route("/keys/{list}/{id}/disable", post(handlers::keys::disable_key_handler))
+    .route("/keys/{list}/{id}/enable", post(handlers::keys::enable_key_handler))
+    .method_not_allowed_fallback(method_not_allowed_fallback)
     .layer(
         from_fn_with_state(
-        Arc::new(config).clone(),
-        auth_middleware))
+        state.clone(),
+        authorization::admin_authorization_middleware));
+
+    let mut app = Router::new()
+    .route("/chat/completions", post(handlers::chat::chat_completions_handler))
+    .layer(
+        from_fn_with_state(
+        state.clone(),
+        authorization::authorization_middleware))
+    // Probes are registered after the authorization layer so they need no credentials
+    .route("/healthz", get(handlers::health::healthz_handler))
+    .route("/readyz", get(handlers::health::readyz_handler))
+    .route("/status", get(handlers::health::status_handler));
+    if listener.metrics {
+        app = app.route("/metrics", get(handlers::health::metrics_handler));
+    }
+    if listener.admin {
+        app = app.nest("/admin", admin);
+    }
+
+    app
+    .method_not_allowed_fallback(method_not_allowed_fallback)
     .fallback(fallback)
-    .with_state(state);
+    .layer(
+        from_fn_with_state(
+        state.clone(),
+        middleware::metrics::metrics_middleware))
+    .layer(axum::middleware::from_fn(middleware::trace::trace_middleware))
+    .layer(axum::middleware::from_fn(middleware::request_id::request_id_middleware))
+    .layer(Extension(listener.clone()))
+    .with_state(state)
+}
 
-    log::info!("listening on {address}");
+/// A listener bound to its address, ready to serve
+enum BoundListener {
+    Tcp(tokio::net::TcpListener),
+    Tls(tls::TlsListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, String),
+}
 
-    let listener = tokio::net::TcpListener::bind(address).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+impl BoundListener {
+    /// Removes the socket file of a Unix domain socket listener
+    fn remove_socket_file(&self) {
+        #[cfg(unix)]
+        if let BoundListener::Unix(_, path) = self {
+            _ = std::fs::remove_file(path);
+        }
+    }
 }
 
-async fn auth_middleware(
-    State(config): State<Arc<cfg>>,
-    request: Request<Body>,
-    next: Next,
-) -> Result<impl IntoResponse, Response> {
-    // Only enforce authorization when listening on "0.0.0.0"
-    if config.host != "0.0.0.0" {
-        return Ok(next.run(request).await);
-    }
-    
-    // If no access keys are configured, allow all requests
-    if config.access_keys.is_empty() {
-        return Ok(next.run(request).await);
-    }
-    
-    // Clone the access keys for validation (to release the lock)
-    let access_keys = config.access_keys.clone();
-    
-    // Extract the Authorization header
-    let headers = request.headers();
-    let auth_header = headers.get(AUTHORIZATION);
-    
-    // Check if Authorization header is missing
-    let auth_header = match auth_header {
-        Some(header) => header,
-        None => return Err(models::AuthorizationError::MissingAuthorizationHeader.into_response()),
-    };
-    
-    // Extract access key from header
-    let access_key = match authorization::extract_access_key_from_header(auth_header) {
-        Some(key) => key,
-        None => return Err(models::AuthorizationError::InvalidAuthorizationScheme.into_response()),
-    };
-    
-    // Validate access key
-    match authorization::validate_access_key(&access_key, &access_keys) {
-        Ok(_) => return Ok(next.run(request).await),
-        Err(error) => return Err(error.into_response()),
-    };    
+/// Binds `listener`, so that every listener can be checked before any serves requests
+async fn bind(listener: &ListenerConfig) -> Result<BoundListener, String> {
+    let label = listener.label();
+
+    #[cfg(unix)]
+    if let Some(path) = &listener.unix_socket {
+        use std::os::unix::fs::FileTypeExt;
+
+        // A socket file left behind by a previous run would make the bind fail
+        if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            _ = std::fs::remove_file(path);
+        }
+        let socket = tokio::net::UnixListener::bind(path).map_err(|e| format!("failed to listen on {label}: {e}"))?;
+        return Ok(BoundListener::Unix(socket, path.clone()));
+    }
+
+    #[cfg(not(unix))]
+    if listener.unix_socket.is_some() {
+        return Err(format!("failed to listen on {label}: Unix domain sockets are not supported on this platform"));
+    }
+
+    let address = listener.address.clone().unwrap_or_default();
+    let socket = tokio::net::TcpListener::bind(&address)
+        .await
+        .map_err(|e| format!("failed to listen on {label}: {e}"))?;
+
+    match listener.tls.clone() {
+        Some(tls) => tls::TlsListener::new(socket, tls)
+            .map(BoundListener::Tls)
+            .map_err(|e| format!("failed to set up TLS on {label}: {e}")),
+        None => Ok(BoundListener::Tcp(socket)),
+    }
 }
 
-async fn chat_completions_handler(
-    State(state): State<ProxyState>,
-    mut headers: HeaderMap,
-    request: axum::http::Request<axum::body::Body>,
-) -> impl IntoResponse {
-    log::info!("/chat/completions");
-
-    let client = Client::new();
-
-    headers.remove("authorization");
-    headers.remove("host");
-    let api_key = state.key_manager.write().unwrap().get_key();
-    let auth_value = format!("Bearer {}", api_key);
-    headers.append("authorization", auth_value.parse().unwrap());
-
-    let base_url = state.config.read().unwrap().base_url.clone();
-    let collected_body = match  request.into_body().collect().await {
-        Ok(collection) => collection.to_bytes(),
-        Err(_) => Bytes::new(),
-    };
+/// Serves requests on a bound listener until the shutdown token is cancelled and the
+/// connections still open are finished
+async fn serve(listener: ListenerConfig, socket: BoundListener, state: ProxyState) -> std::io::Result<()> {
+    let listener = Arc::new(listener);
+    let shutdown = state.shutdown.clone().cancelled_owned();
+    let app = build_router(&listener, state).into_make_service_with_connect_info::<PeerInfo>();
+    log::info!("listening on {}{}", listener.label(), if listener.tls.is_some() { " with TLS" } else { "" });
+
+    match socket {
+        BoundListener::Tcp(socket) => axum::serve(socket, app).with_graceful_shutdown(shutdown).await,
+        BoundListener::Tls(socket) => axum::serve(socket, app).with_graceful_shutdown(shutdown).await,
+        #[cfg(unix)]
+        BoundListener::Unix(socket, path) => {
+            let served = axum::serve(socket, app).with_graceful_shutdown(shutdown).await;
+            _ = std::fs::remove_file(path);
+            served
+        }
+    }
+}
+
+/// Reports a startup failure on one line and exits with status 1
+fn exit_on_startup_error(message: impl std::fmt::Display) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1);
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    match cli.command() {
+        Command::HashKey { key } => {
+            hash_key_command(key.clone());
+            return;
+        }
+        Command::EncryptSecret { secret } => {
+            encrypt_secret_command(secret.clone());
+            return;
+        }
+        Command::GenerateMasterKey => {
+            println!("{}", secrets::generate_master_key());
+            return;
+        }
+        Command::PrintDefaultConfig => {
+            print!("{}", cli::DEFAULT_CONFIG);
+            return;
+        }
+        Command::Serve | Command::CheckConfig => {}
+    }
+    cfg::set_overrides(cli.overrides());
 
-    let req = client
-        .post(format!("{base_url}/chat/completions",))
-        .headers(headers)
-        .body(collected_body);
-    let data = req.build().unwrap();
-    let res = match client.execute(data).await {
-        Ok(res) => res,
+    // Validate before the logger is set up so that errors are reported on stderr
+    let config = match cfg::load() {
+        Ok(config) => config,
         Err(e) => {
-            log::error!("{}", e);
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    // On stderr so that it shows regardless of the log level
+    for warning in config.auth_warnings() {
+        eprintln!("warning: {warning}");
+    }
+    if let Command::CheckConfig = cli.command() {
+        println!("configuration is valid");
+        return;
+    }
 
-            let mut axum_response = Response::new(Body::from(e.to_string()));
-            *axum_response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+    let tracer_provider = match logger::init(&config) {
+        Ok(provider) => provider,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    if let Some(telemetry) = &config.telemetry {
+        log::info!("exporting traces to {}", telemetry.otlp_endpoint);
+    }
+    if config.access_keys.iter().chain(config.admin_keys.iter()).any(|entry| entry.source.is_none() && !key_hashing::is_hashed(&entry.key)) {
+        log::warn!("plaintext access keys are configured; run `openai-proxy hash-key` to generate hashed entries");
+    }
+    let km = KeyManager::new(config.api_keys.clone());
 
-            return axum_response;
+    let usage_store: Option<Arc<dyn UsageStore>> = match &config.usage_db_path {
+        Some(path) => match SqliteUsageStore::open(path) {
+            Ok(store) => {
+                log::info!("recording usage to {path}");
+                Some(Arc::new(store))
+            }
+            Err(e) => exit_on_startup_error(format!("failed to open usage store {path}: {e}")),
         },
-    }; 
-    let is_stream = if let Some(header_value) = res.headers().get("content-type") {
-        match header_value.to_str() {
-            Ok(s) => s == "text/event-stream",
-            Err(_) => false,
+        None => None,
+    };
+
+    let activity_logger = match &config.acivity_logging_path {
+        Some(path) => {
+            let max_bytes = config.activity_log_max_bytes.unwrap_or(activity_log::DEFAULT_MAX_BYTES);
+            let max_files = config.activity_log_max_files.unwrap_or(activity_log::DEFAULT_MAX_FILES);
+            let redactor = match Redactor::new(&config) {
+                Ok(redactor) => Arc::new(redactor),
+                Err(e) => exit_on_startup_error(e),
+            };
+            match ActivityLogger::spawn(path, max_bytes, max_files, redactor) {
+                Ok(logger) => {
+                    log::info!("writing activity log to {path}");
+                    Some(logger)
+                }
+                Err(e) => exit_on_startup_error(format!("failed to open activity log {path}: {e}")),
+            }
         }
-    } else { false };
-    let headers = res.headers().clone(); 
-    let status = res.status();
+        None => None,
+    };
 
-    log::info!("---!!! response status: {}", status.as_str());
+    let state =  ProxyState::new(config.clone(), km, usage_store, activity_logger).await;
 
-    if !status.is_success() {   
-        if status == StatusCode::TOO_MANY_REQUESTS {
-            // switch to next key
-            state.key_manager.write().unwrap().switch_key();
+    // Every listener is bound before any serves, so a taken port fails the start cleanly
+    let mut bound = Vec::new();
+    for listener in config.listeners() {
+        match bind(&listener).await {
+            Ok(socket) => bound.push((listener, socket)),
+            Err(e) => {
+                for (_, socket) in &bound {
+                    socket.remove_socket_file();
+                }
+                exit_on_startup_error(e);
+            }
         }
     }
 
-    let body = if is_stream {
-        log::debug!("--->>> stream response");
-        let stream = async_stream::stream! {
-            let mut stream = res.bytes_stream();
+    key_expiry::spawn_expiry_monitor(state.clone());
+    config_reload::spawn_config_watcher(state.clone());
 
-            while let Some(item) = stream.next().await {
-                log::trace!("---+++ chank");
-                yield Ok::<_, axum::Error>(item.unwrap());                
-            }         
-        };
+    shutdown::spawn_signal_handler(state.clone());
+
+    let mut servers = tokio::task::JoinSet::new();
+    for (listener, socket) in bound {
+        servers.spawn(serve(listener, socket, state.clone()));
+    }
+
+    let mut failed = false;
+    tokio::select! {
+        _ = state.shutdown.cancelled() => {}
+        Some(result) = servers.join_next() => {
+            // A listener only stops on its own when it fails
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => log::error!("a listener failed: {e}"),
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            }
+            failed = true;
+            state.shutdown.cancel();
+        }
+    }
+
+    let drain_timeout = shutdown::drain_timeout(&state);
+    log::info!("shutting down, waiting up to {}s for in-flight requests", drain_timeout.as_secs());
+    let drained = tokio::time::timeout(drain_timeout, async {
+        while servers.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_err() {
+        log::warn!("drain timeout elapsed, closing the remaining connections");
+    }
+
+    shutdown::flush_logs(&state).await;
+    if let Some(provider) = tracer_provider {
+        // Exporting the buffered spans blocks on the HTTP client
+        let flushed = tokio::task::spawn_blocking(move || provider.shutdown()).await;
+        if let Ok(Err(e)) = flushed {
+            log::warn!("failed to export the remaining spans: {e}");
+        }
+    }
+    log::info!("shutdown complete");
+    if failed {
+        std::process::exit(1);
+    }
+}
 
-        Body::from_stream(stream)        
-    } else {
-        log::debug!("--->>> single response");
-        let body_bytes = res.bytes().await.unwrap();
-        Body::from(body_bytes)
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use axum::{
+        body::{Body, Bytes},
+        http::{HeaderMap, Request, StatusCode},
     };
+    use figment::{providers::{Format, Toml}, Figment};
+    use tower::ServiceExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::models::config::TelemetryConfig;
+
+    const TRACE_ID: &str = "0af7651916cd43dd8448eb211c80319c";
+
+    /// Serves `router` on a local port and returns its address
+    async fn spawn_server(router: Router) -> std::net::SocketAddr {
+        let socket = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(socket, router).await });
+        address
+    }
+
+    #[tokio::test]
+    async fn exports_spans_and_propagates_traceparent_upstream() {
+        // Stand-ins for the OTLP collector and the upstream API
+        let exported: Arc<Mutex<Vec<Bytes>>> = Arc::default();
+        let collector = {
+            let exported = exported.clone();
+            Router::new().route("/v1/traces", post(move |body: Bytes| async move {
+                exported.lock().unwrap().push(body);
+                StatusCode::OK
+            }))
+        };
+        let collector = spawn_server(collector).await;
+        let upstream_headers: Arc<Mutex<Option<HeaderMap>>> = Arc::default();
+        let upstream = {
+            let upstream_headers = upstream_headers.clone();
+            Router::new().route("/chat/completions", post(move |headers: HeaderMap| async move {
+                *upstream_headers.lock().unwrap() = Some(headers);
+                axum::Json(serde_json::json!({ "object": "chat.completion", "choices": [] }))
+            }))
+        };
+        let upstream = spawn_server(upstream).await;
+
+        let config: cfg = Figment::from(Toml::string(&format!(
+            r#"
+            version = "test"
+            host = "127.0.0.1"
+            port = 8080
+            base_url = "http://{upstream}"
+            api_keys = ["sk-test"]
+            access_keys = []
+            auth = {{ mode = "disabled" }}
+            "#
+        )))
+        .extract()
+        .unwrap();
+        let provider = telemetry::build_provider(&TelemetryConfig {
+            otlp_endpoint: format!("http://{collector}/v1/traces"),
+            service_name: None,
+            sample_ratio: None,
+        })
+        .unwrap();
+        // The test runs on a single thread, so a thread-local subscriber sees every span
+        let _subscriber = tracing::subscriber::set_default(
+            tracing_subscriber::registry()
+                .with(tracing_opentelemetry::layer().with_tracer(telemetry::tracer(&provider))),
+        );
 
-    let mut axum_response = Response::new(body);
-    *axum_response.status_mut() = status;
-    *axum_response.headers_mut() = headers;
+        let listener = Arc::new(config.listeners().remove(0));
+        let key_manager = KeyManager::new(config.api_keys.clone());
+        let state = ProxyState::new(config, key_manager, None, None).await;
+        let response = build_router(&listener, state)
+            .oneshot(
+                Request::post("/chat/completions")
+                    .header("traceparent", format!("00-{TRACE_ID}-b7ad6b7169203331-01"))
+                    .body(Body::from(r#"{"model":"gpt-test"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-    axum_response
+        let traceparent = upstream_headers.lock().unwrap().as_ref().unwrap()["traceparent"].to_str().unwrap().to_string();
+        assert!(traceparent.starts_with(&format!("00-{TRACE_ID}-")), "{traceparent}");
+        assert!(!traceparent.contains("b7ad6b7169203331"), "{traceparent}");
+
+        tokio::task::spawn_blocking(move || provider.shutdown()).await.unwrap().unwrap();
+        let exported = exported.lock().unwrap().concat();
+        let trace_id: Vec<u8> = (0..TRACE_ID.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&TRACE_ID[index..index + 2], 16).unwrap())
+            .collect();
+        for expected in [&b"POST /chat/completions"[..], b"select_upstream_key", b"upstream_request", &trace_id] {
+            assert!(exported.windows(expected.len()).any(|window| window == expected));
+        }
+    }
+
+    #[tokio::test]
+    async fn renders_wrong_methods_as_openai_errors() {
+        let config: cfg = Figment::from(Toml::string(
+            r#"
+            version = "test"
+            host = "127.0.0.1"
+            port = 8080
+            base_url = "http://127.0.0.1:9"
+            api_keys = ["sk-test"]
+            access_keys = []
+            admin_keys = ["adm"]
+            auth = { mode = "disabled" }
+            "#,
+        ))
+        .extract()
+        .unwrap();
+        let listener = Arc::new(config.listeners().remove(0));
+        let key_manager = KeyManager::new(config.api_keys.clone());
+        let router = build_router(&listener, ProxyState::new(config, key_manager, None, None).await);
+
+        for path in ["/chat/completions", "/admin/keys/upstream"] {
+            let response = router
+                .clone()
+                .oneshot(Request::put(path).header("authorization", "Bearer adm").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["error"]["code"], "method_not_allowed");
+            assert_eq!(body["error"]["message"], format!("Method PUT is not allowed for {path}"));
+        }
+    }
+
+    #[tokio::test]
+    async fn ends_interrupted_streams_with_an_error_event() {
+        // A partially sent event is terminated before the error event
+        let cases = [
+            ("data: {\"id\":\"1\"}\r\n\r\n", ""),
+            ("data: {\"id\":\"1\"}\r\n\r\ndata: {\"id", "\n\n"),
+        ];
+        for (sent, separator) in cases {
+            // The upstream sends part of the stream, then drops the connection
+            let upstream = Router::new().route("/chat/completions", post(move || async move {
+                let chunks = async_stream::stream! {
+                    yield Ok(Bytes::from(sent));
+                    // Lets the headers and the first chunk reach the proxy
+                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                    yield Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "dropped"));
+                };
+                ([("content-type", "text/event-stream")], Body::from_stream(chunks))
+            }));
+            let upstream = spawn_server(upstream).await;
+            let config: cfg = Figment::from(Toml::string(&format!(
+                r#"
+                version = "test"
+                host = "127.0.0.1"
+                port = 8080
+                base_url = "http://{upstream}"
+                api_keys = ["sk-test"]
+                access_keys = []
+                auth = {{ mode = "disabled" }}
+                "#
+            )))
+            .extract()
+            .unwrap();
+            let listener = Arc::new(config.listeners().remove(0));
+            let key_manager = KeyManager::new(config.api_keys.clone());
+            let router = build_router(&listener, ProxyState::new(config, key_manager, None, None).await);
+
+            let response = router
+                .oneshot(Request::post("/chat/completions").body(Body::from(r#"{"stream":true}"#)).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body = std::str::from_utf8(&body).unwrap();
+
+            let rest = body.strip_prefix(sent).and_then(|rest| rest.strip_prefix(separator)).unwrap();
+            let (event, rest) = rest.strip_prefix("data: ").unwrap().split_once("\n\n").unwrap();
+            let event: serde_json::Value = serde_json::from_str(event).unwrap();
+            assert_eq!(event["error"]["code"], "upstream_interrupted");
+            assert_eq!(rest, "data: [DONE]\n\n");
+        }
+    }
 }
@@ -0,0 +1,144 @@
+//! Prometheus metrics for the OpenAI Proxy Carousel
+//!
+//! Counters and histograms are updated as requests pass through the proxy: per route
+//! by the metrics middleware, and per upstream exchange (model, upstream key, tokens,
+//! cost) by the exchange tracker once the response is complete. The upstream key pool
+//! is sampled when `/metrics` is scraped. Upstream keys are labelled by fingerprint.
+
+use prometheus::{
+    CounterVec, Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+
+use crate::key_manager::KeyPoolHealth;
+
+/// Buckets in seconds for request durations, up to the length of a long completion
+const DURATION_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+/// All metrics exposed on `/metrics`
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub in_flight_requests: IntGauge,
+    pub upstream_requests: IntCounterVec,
+    pub upstream_request_duration: HistogramVec,
+    pub time_to_first_token: HistogramVec,
+    pub stream_errors: IntCounterVec,
+    pub tokens: IntCounterVec,
+    pub cost: CounterVec,
+    pub rate_limited: IntCounterVec,
+    pub key_switches: IntCounter,
+    pub upstream_keys: IntGaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some("proxy".to_string()), None)?;
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Requests by route and response status"),
+            &["route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time until the response headers were sent, by route",
+            )
+            .buckets(DURATION_BUCKETS.to_vec()),
+            &["route"],
+        )?;
+        let in_flight_requests = IntGauge::new(
+            "in_flight_requests",
+            "Requests sent upstream whose response, including streams, is not complete",
+        )?;
+        let upstream_requests = IntCounterVec::new(
+            Opts::new("upstream_requests_total", "Requests sent upstream by model, status and upstream key"),
+            &["model", "status", "upstream_key"],
+        )?;
+        let upstream_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "upstream_request_duration_seconds",
+                "Time until the upstream response, including streams, was complete",
+            )
+            .buckets(DURATION_BUCKETS.to_vec()),
+            &["model"],
+        )?;
+        let time_to_first_token = HistogramVec::new(
+            HistogramOpts::new(
+                "time_to_first_token_seconds",
+                "Time until the first chunk of a streamed response arrived",
+            )
+            .buckets(DURATION_BUCKETS.to_vec()),
+            &["model"],
+        )?;
+        let stream_errors = IntCounterVec::new(
+            Opts::new("upstream_stream_errors_total", "Streamed responses cut short by an upstream failure, by model"),
+            &["model"],
+        )?;
+        let tokens = IntCounterVec::new(
+            Opts::new("tokens_total", "Tokens reported by the upstream, by model and kind"),
+            &["model", "kind"],
+        )?;
+        let cost = CounterVec::new(
+            Opts::new("cost_usd_total", "Cost computed from the configured pricing, by model"),
+            &["model"],
+        )?;
+        let rate_limited = IntCounterVec::new(
+            Opts::new("upstream_rate_limited_total", "429 responses by upstream key"),
+            &["upstream_key"],
+        )?;
+        let key_switches = IntCounter::new(
+            "upstream_key_switches_total",
+            "Switches to the next upstream key after a 429 response",
+        )?;
+        let upstream_keys = IntGaugeVec::new(
+            Opts::new("upstream_keys", "Upstream keys by state"),
+            &["state"],
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(in_flight_requests.clone()))?;
+        registry.register(Box::new(upstream_requests.clone()))?;
+        registry.register(Box::new(upstream_request_duration.clone()))?;
+        registry.register(Box::new(time_to_first_token.clone()))?;
+        registry.register(Box::new(stream_errors.clone()))?;
+        registry.register(Box::new(tokens.clone()))?;
+        registry.register(Box::new(cost.clone()))?;
+        registry.register(Box::new(rate_limited.clone()))?;
+        registry.register(Box::new(key_switches.clone()))?;
+        registry.register(Box::new(upstream_keys.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            in_flight_requests,
+            upstream_requests,
+            upstream_request_duration,
+            time_to_first_token,
+            stream_errors,
+            tokens,
+            cost,
+            rate_limited,
+            key_switches,
+            upstream_keys,
+        })
+    }
+
+    /// Updates the key pool gauges and renders all metrics in the text format
+    pub fn render(&self, keys: &KeyPoolHealth) -> String {
+        self.upstream_keys.with_label_values(&["usable"]).set(keys.usable as i64);
+        self.upstream_keys.with_label_values(&["disabled"]).set(keys.disabled as i64);
+        self.upstream_keys.with_label_values(&["expired"]).set(keys.expired as i64);
+        self.upstream_keys.with_label_values(&["pending"]).set(keys.pending as i64);
+
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            log::error!("failed to encode metrics: {}", e);
+        }
+
+        String::from_utf8_lossy(&buffer).into_owned()
+    }
+}
@@ -1,14 +1,27 @@
 //! Authorization middleware for the OpenAI Proxy Carousel
 //!
-//! This module contains middleware for validating access keys in the Authorization header
-//! when the server is configured to listen on "0.0.0.0".
+//! This module contains middleware for validating access keys, JWT bearer tokens or
+//! TLS client certificates according to `auth.mode`, and for the admin keys that guard
+//! the `/admin` endpoints.
+
+use std::{collections::HashMap, sync::Arc};
 
 use axum::{
-    body::Body, http::Request, middleware::Next, response::{IntoResponse, Response}
+    body::Body,
+    extract::{ConnectInfo, Query, State},
+    http::{Request, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
 };
 use axum::http::header::{AUTHORIZATION, HeaderValue};
+use chrono::Utc;
+use tracing::Instrument;
 
-use crate::models::{AuthorizationError, ValidatedAccessKey};
+use crate::jwt::{looks_like_jwt, JwtVerifier};
+use crate::key_hashing::verify_key;
+use crate::models::config::{AccessKey, AuthMode, CertificateIdentity, ClientAuthConfig, CredentialSource, ListenerConfig};
+use crate::models::{AuthorizationError, ClientIdentity, IdentityKind, ValidatedAccessKey};
+use crate::peer::PeerInfo;
 use crate::state::State as ProxyState;
 
 /// Extracts the access key from the Authorization header
@@ -23,15 +36,14 @@ use crate::state::State as ProxyState;
 /// * `None` - If the header doesn't use the Bearer scheme or is invalid
 pub fn extract_access_key_from_header(header_value: &HeaderValue) -> Option<String> {
     let header_str = header_value.to_str().ok()?;
-    if header_str.starts_with("Bearer ") {
-        Some(header_str[7..].to_string())
-    } else {
-        None
-    }
+    header_str.strip_prefix("Bearer ").map(str::to_string)
 }
 
 /// Validates an access key against the configured access keys
 ///
+/// Entries may be plaintext or `sha256:` hashes; every entry is checked in
+/// constant time so the response time does not reveal which one matched.
+///
 /// # Arguments
 ///
 /// * `access_key` - The access key to validate
@@ -41,83 +53,393 @@ pub fn extract_access_key_from_header(header_value: &HeaderValue) -> Option<Stri
 ///
 /// * `Ok(ValidatedAccessKey)` - If the access key is valid
 /// * `Err(AuthorizationError::Unauthorized)` - If the access key is invalid
+/// * `Err(AuthorizationError::AccessKeyExpired)` - If the access key is past its `expires_at`
+/// * `Err(AuthorizationError::AccessKeyNotYetValid)` - If the access key is before its `not_before`
+/// * `Err(AuthorizationError::AccessKeyDisabled)` - If the access key has been disabled
 pub fn validate_access_key(
     access_key: &str,
-    configured_keys: &[String],
+    configured_keys: &[AccessKey],
 ) -> Result<ValidatedAccessKey, AuthorizationError> {
-    if configured_keys.contains(&access_key.to_string()) {
-        Ok(ValidatedAccessKey {
-            key: access_key.to_string(),
-        })
-    } else {
-        Err(AuthorizationError::Unauthorized)
+    let matched = configured_keys.iter().fold(None, |matched, entry| {
+        if verify_key(access_key, &entry.key) { Some(entry) } else { matched }
+    });
+
+    let Some(entry) = matched else {
+        return Err(AuthorizationError::Unauthorized);
+    };
+
+    if entry.disabled {
+        log::warn!("{} used while disabled", entry.label());
+        return Err(AuthorizationError::AccessKeyDisabled);
+    }
+
+    let now = Utc::now();
+    if entry.not_before.is_some_and(|not_before| now < not_before) {
+        log::warn!("{} used before its not_before", entry.label());
+        return Err(AuthorizationError::AccessKeyNotYetValid);
+    }
+    if entry.expires_at.is_some_and(|expires_at| now >= expires_at) {
+        log::warn!("{} used after it expired", entry.label());
+        return Err(AuthorizationError::AccessKeyExpired);
+    }
+
+    Ok(ValidatedAccessKey {
+        key: access_key.to_string(),
+        owner: entry.owner.clone(),
+    })
+}
+
+/// Extracts the Bearer token of a request
+fn extract_bearer_token(request: &Request<Body>) -> Result<String, AuthorizationError> {
+    // Extract the Authorization header
+    let auth_header = request
+        .headers()
+        .get(AUTHORIZATION)
+        .ok_or(AuthorizationError::MissingAuthorizationHeader)?;
+
+    // Extract access key from header
+    extract_access_key_from_header(auth_header).ok_or(AuthorizationError::InvalidAuthorizationScheme)
+}
+
+/// Extracts and validates the Bearer key of a request against `configured_keys`
+fn authorize_request(
+    request: &Request<Body>,
+    configured_keys: &[AccessKey],
+) -> Result<ValidatedAccessKey, AuthorizationError> {
+    let access_key = extract_bearer_token(request)?;
+    validate_access_key(&access_key, configured_keys)
+}
+
+/// Returns the value of the query string parameter `name`
+fn query_parameter(uri: &Uri, name: &str) -> Option<String> {
+    let Query(parameters) = Query::<HashMap<String, String>>::try_from_uri(uri).ok()?;
+    parameters.get(name).cloned()
+}
+
+/// Reads the client credential from the first of `sources` present in the request
+///
+/// # Returns
+///
+/// * `Ok(String)` - The credential
+/// * `Err(AuthorizationError::MissingAuthorizationHeader)` - If no source is present
+/// * `Err(AuthorizationError::InvalidAuthorizationScheme)` - If the first present source is malformed
+fn extract_credential(
+    request: &Request<Body>,
+    sources: &[CredentialSource],
+) -> Result<String, AuthorizationError> {
+    for source in sources {
+        match source {
+            CredentialSource::Authorization => {
+                if let Some(header) = request.headers().get(AUTHORIZATION) {
+                    return extract_access_key_from_header(header)
+                        .ok_or(AuthorizationError::InvalidAuthorizationScheme);
+                }
+            }
+            CredentialSource::Header(name) => {
+                if let Some(header) = request.headers().get(name.as_str()) {
+                    return header
+                        .to_str()
+                        .map(|value| value.trim().to_string())
+                        .map_err(|_| AuthorizationError::InvalidAuthorizationScheme);
+                }
+            }
+            CredentialSource::Query(name) => {
+                if let Some(value) = query_parameter(request.uri(), name) {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+
+    Err(AuthorizationError::MissingAuthorizationHeader)
+}
+
+/// Returns whether any of `sources` is present in the request
+fn has_credentials(request: &Request<Body>, sources: &[CredentialSource]) -> bool {
+    sources.iter().any(|source| match source {
+        CredentialSource::Authorization => request.headers().contains_key(AUTHORIZATION),
+        CredentialSource::Header(name) => request.headers().contains_key(name.as_str()),
+        CredentialSource::Query(name) => query_parameter(request.uri(), name).is_some(),
+    })
+}
+
+/// Removes every credential source from the request so none is forwarded upstream
+fn strip_credentials(request: &mut Request<Body>, sources: &[CredentialSource]) {
+    for source in sources {
+        match source {
+            CredentialSource::Authorization => {
+                request.headers_mut().remove(AUTHORIZATION);
+            }
+            CredentialSource::Header(name) => {
+                request.headers_mut().remove(name.as_str());
+            }
+            CredentialSource::Query(name) => {
+                let Some(query) = request.uri().query() else {
+                    continue;
+                };
+                let remaining: Vec<&str> = query
+                    .split('&')
+                    .filter(|pair| pair.split('=').next() != Some(name.as_str()))
+                    .collect();
+
+                let path = request.uri().path();
+                let path_and_query = if remaining.is_empty() {
+                    path.to_string()
+                } else {
+                    format!("{}?{}", path, remaining.join("&"))
+                };
+
+                let mut parts = request.uri().clone().into_parts();
+                match path_and_query.parse() {
+                    Ok(path_and_query) => parts.path_and_query = Some(path_and_query),
+                    Err(_) => continue,
+                }
+                if let Ok(uri) = Uri::from_parts(parts) {
+                    *request.uri_mut() = uri;
+                }
+            }
+        }
     }
 }
 
+/// Authenticates a client by its credential, either a JWT or an access key
+async fn authenticate_client(
+    token: String,
+    access_keys: &[AccessKey],
+    jwt_verifier: Option<&JwtVerifier>,
+) -> Result<ClientIdentity, AuthorizationError> {
+    match jwt_verifier {
+        Some(verifier) if looks_like_jwt(&token) => verifier.verify(&token).await,
+        _ => validate_access_key(&token, access_keys).map(ClientIdentity::from),
+    }
+}
+
+/// Maps the verified client certificate of the connection to an identity
+///
+/// # Returns
+///
+/// * `None` - If the connection has no client certificate
+/// * `Some(Ok(ClientIdentity))` - If the certificate identity is allowed
+/// * `Some(Err(AuthorizationError::Forbidden))` - If the certificate has no usable identity or it is not allowed
+fn certificate_identity(
+    request: &Request<Body>,
+    client_auth: &ClientAuthConfig,
+) -> Option<Result<ClientIdentity, AuthorizationError>> {
+    let ConnectInfo(peer) = request.extensions().get::<ConnectInfo<PeerInfo>>()?;
+    let certificate = peer.client_certificate.as_ref()?;
+
+    let identity = match client_auth.identity_from {
+        CertificateIdentity::San => certificate.subject_alt_names.first(),
+        CertificateIdentity::CommonName => certificate.common_name.as_ref(),
+    };
+    let Some(identity) = identity else {
+        log::warn!("rejected client certificate without a {:?} identity", client_auth.identity_from);
+        return Some(Err(AuthorizationError::Forbidden));
+    };
+    if !client_auth.allowed_identities.is_empty() && !client_auth.allowed_identities.contains(identity) {
+        log::warn!("rejected client certificate for {}: identity is not allowed", identity);
+        return Some(Err(AuthorizationError::Forbidden));
+    }
+
+    Some(Ok(ClientIdentity {
+        id: identity.clone(),
+        kind: IdentityKind::Certificate,
+        groups: client_auth.groups.get(identity).cloned().unwrap_or_default(),
+        owner: None,
+    }))
+}
+
+/// Returns whether the request comes from a loopback peer; Unix domain socket peers
+/// are on the same host and count as loopback
+fn is_loopback_peer(request: &Request<Body>) -> bool {
+    request
+        .extensions()
+        .get::<ConnectInfo<PeerInfo>>()
+        .is_some_and(|ConnectInfo(peer)| {
+            peer.address.is_none_or(|address| address.ip().to_canonical().is_loopback())
+        })
+}
+
 /// Authorization middleware that validates access keys
 ///
-/// This middleware only enforces authorization when the server is configured
+/// Enforcement follows `auth.mode`: `required` rejects requests without a valid key,
+/// `optional` lets requests without credentials through anonymously and `disabled`
+/// skips all checks; a listener's `auth_mode` overrides it. A loopback listener without
+/// any configured credentials lets every request through, as the proxy did before
+/// `auth.mode` existed. With `auth.exempt_loopback`, requests from loopback peers may
+/// omit credentials, but credentials they present are checked. Credentials are read from the first of
+/// `auth.credential_sources` present in the request, and all of those sources are
+/// removed before the request reaches the handler. Tokens shaped like a JWT are verified
+/// against `auth.jwt` when it is configured. Without credentials, a client certificate
+/// verified by `tls.client_auth` identifies the client. The resulting [`ClientIdentity`] is
+/// stored in the request extensions so that handlers can attribute usage to it.
 ///
 /// # Arguments
 ///
+/// * `state` - The proxy state holding the configuration
 /// * `request` - The incoming request
 /// * `next` - The next middleware in the chain
 ///
 /// # Returns
 ///
 /// * `Response` - The response from the next middleware or an error response
-
 pub async fn authorization_middleware(
-    request: Request<Body>,
+    State(state): State<ProxyState>,
+    mut request: Request<Body>,
     next: Next,
 ) -> Result<impl IntoResponse, Response> {
-    // Get the state from the request extensions
-    let state = request.extensions().get::<ProxyState>();
-    
-    // If we can't get the state, allow the request to proceed
-    let state = match state {
-        Some(s) => s,
-        None => return Ok(next.run(request).await),
+    // Clone what we need so the read lock is released before awaiting
+    let listener = request.extensions().get::<Arc<ListenerConfig>>().cloned();
+    let (mut auth, access_keys, lacks_credentials) = {
+        let config = state.config.read().unwrap();
+        let lacks_credentials = listener.as_ref().is_some_and(|listener| config.lacks_credentials(listener));
+        (config.auth.clone(), config.access_keys.clone(), lacks_credentials)
     };
-    
-    // Get the configuration
-    let config = state.config.read().unwrap();
-    
-    // Only enforce authorization when listening on "0.0.0.0"
-    if config.host != "0.0.0.0" {
-        drop(config); // Release the read lock
-        return Ok(next.run(request).await);
-    }
-    
-    // If no access keys are configured, allow all requests
-    if config.access_keys.is_empty() {
-        drop(config); // Release the read lock
-        return Ok(next.run(request).await);
+    if let Some(mode) = listener.as_ref().and_then(|listener| listener.auth_mode) {
+        auth.mode = mode;
     }
-    
-    // Clone the access keys for validation (to release the lock)
-    let access_keys = config.access_keys.clone();
-    drop(config); // Release the read lock
-    
-    // Extract the Authorization header
-    let headers = request.headers();
-    let auth_header = headers.get(AUTHORIZATION);
-    
-    // Check if Authorization header is missing
-    let auth_header = match auth_header {
-        Some(header) => header,
-        None => return Err(AuthorizationError::MissingAuthorizationHeader.into_response()),
-    };
-    
-    // Extract access key from header
-    let access_key = match extract_access_key_from_header(auth_header) {
-        Some(key) => key,
-        None => return Err(AuthorizationError::InvalidAuthorizationScheme.into_response()),
+    let client_auth = listener.as_ref().and_then(|listener| listener.tls.as_ref()?.client_auth.as_ref());
+    let sources = &auth.credential_sources;
+    let span = tracing::info_span!("authorize", auth.mode = ?auth.mode, client.kind = tracing::field::Empty);
+
+    let certificate = match client_auth {
+        Some(client_auth) if auth.mode != AuthMode::Disabled && !has_credentials(&request, sources) => {
+            certificate_identity(&request, client_auth)
+        }
+        _ => None,
     };
-    
-    // Validate access key
-    match validate_access_key(&access_key, &access_keys) {
-        Ok(_) => return Ok(next.run(request).await),
-        Err(error) => return Err(error.into_response()),
-    };    
-}
\ No newline at end of file
+
+    // Validation only lets a listener without credentials through on loopback addresses
+    // Presented credentials are checked even where they may be omitted
+    let anonymous = auth.mode == AuthMode::Disabled
+        || lacks_credentials
+        || (!has_credentials(&request, sources)
+            && (auth.mode == AuthMode::Optional || (auth.exempt_loopback && is_loopback_peer(&request))));
+
+    if let Some(identity) = certificate {
+        let identity = identity.map_err(IntoResponse::into_response)?;
+        span.record("client.kind", tracing::field::debug(identity.kind));
+        request.extensions_mut().insert(identity);
+    } else if !anonymous {
+        let credential = extract_credential(&request, sources).map_err(IntoResponse::into_response)?;
+        let identity = authenticate_client(credential, &access_keys, state.jwt_verifier.as_deref())
+            .instrument(span.clone())
+            .await
+            .map_err(IntoResponse::into_response)?;
+        span.record("client.kind", tracing::field::debug(identity.kind));
+        request.extensions_mut().insert(identity);
+    }
+
+    strip_credentials(&mut request, sources);
+    // End the span before the handler runs, it only covers the authorization
+    drop(span);
+
+    Ok(next.run(request).await)
+}
+
+/// Authorization middleware for the `/admin` endpoints
+///
+/// Unlike [`authorization_middleware`] this is enforced regardless of the listen
+/// address, and rejects every request when no admin keys are configured.
+pub async fn admin_authorization_middleware(
+    State(state): State<ProxyState>,
+    request: Request<Body>,
+    next: Next,
+) -> Result<impl IntoResponse, Response> {
+    let admin_keys = state.config.read().unwrap().admin_keys.clone();
+
+    if admin_keys.is_empty() {
+        return Err(AuthorizationError::AdminApiDisabled.into_response());
+    }
+
+    authorize_request(&request, &admin_keys).map_err(IntoResponse::into_response)?;
+
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, middleware::from_fn_with_state, routing::get, Router};
+    use figment::{providers::{Format, Toml}, Figment};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::key_manager::KeyManager;
+    use crate::models::config::Config;
+
+    fn sources() -> Vec<CredentialSource> {
+        ["authorization", "x-api-key", "query:key"]
+            .into_iter()
+            .map(|source| CredentialSource::try_from(source.to_string()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn strips_every_credential_source() {
+        let mut request = Request::builder()
+            .uri("/v1/chat/completions?api-version=1&key=secret&x=2")
+            .header(AUTHORIZATION, "Bearer ak-1")
+            .header("x-api-key", "ak-2")
+            .header("x-request-id", "abc")
+            .body(Body::empty())
+            .unwrap();
+
+        strip_credentials(&mut request, &sources());
+
+        assert!(!request.headers().contains_key(AUTHORIZATION));
+        assert!(!request.headers().contains_key("x-api-key"));
+        assert_eq!(request.headers()["x-request-id"], "abc");
+        assert_eq!(request.uri(), "/v1/chat/completions?api-version=1&x=2");
+        assert!(!has_credentials(&request, &sources()));
+    }
+
+    #[test]
+    fn strips_a_query_that_only_holds_the_credential() {
+        let mut request = Request::builder()
+            .uri("/v1/models?key=secret")
+            .body(Body::empty())
+            .unwrap();
+
+        strip_credentials(&mut request, &sources());
+
+        assert_eq!(request.uri(), "/v1/models");
+    }
+
+    #[tokio::test]
+    async fn checks_credentials_presented_by_exempt_loopback_peers() {
+        let config: Config = Figment::from(Toml::string(
+            r#"
+            version = "test"
+            host = "127.0.0.1"
+            port = 8080
+            base_url = "http://127.0.0.1:9"
+            api_keys = ["sk-test"]
+            access_keys = ["ak-1"]
+            auth = { mode = "required", exempt_loopback = true }
+            "#,
+        ))
+        .extract()
+        .unwrap();
+        let km = KeyManager::new(config.api_keys.clone());
+        let state = ProxyState::new(config, km, None, None).await;
+        let router = Router::new()
+            .route("/", get(|| async { StatusCode::OK }))
+            .layer(from_fn_with_state(state, authorization_middleware));
+        let loopback = ConnectInfo(PeerInfo { address: Some(([127, 0, 0, 1], 40000).into()), client_certificate: None });
+
+        let status = |authorization: Option<&str>| {
+            let mut request = Request::builder().uri("/");
+            if let Some(authorization) = authorization {
+                request = request.header(AUTHORIZATION, authorization);
+            }
+            let mut request = request.body(Body::empty()).unwrap();
+            request.extensions_mut().insert(loopback.clone());
+            let router = router.clone();
+            async move { router.oneshot(request).await.unwrap().status() }
+        };
+
+        assert_eq!(status(None).await, StatusCode::OK);
+        assert_eq!(status(Some("Bearer ak-1")).await, StatusCode::OK);
+        assert_eq!(status(Some("Bearer wrong")).await, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,49 @@
+//! Metrics middleware for the OpenAI Proxy Carousel
+//!
+//! Counts requests by matched route and status, and measures the time until the
+//! response headers are sent. Streamed bodies are measured by the exchange tracker.
+
+use std::time::Instant;
+
+use axum::{
+    body::Body,
+    extract::{MatchedPath, State},
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+
+use crate::state::State as ProxyState;
+
+/// The matched route of a request; unmatched paths share one label so that scanners
+/// cannot inflate the cardinality
+pub fn route_label(request: &Request<Body>) -> String {
+    request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| "unmatched".to_string(), |path| path.as_str().to_string())
+}
+
+/// Records the route, status and duration of every request
+pub async fn metrics_middleware(
+    State(state): State<ProxyState>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let route = route_label(&request);
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    let metrics = &state.metrics;
+    metrics
+        .http_requests
+        .with_label_values(&[route.as_str(), response.status().as_str()])
+        .inc();
+    metrics
+        .http_request_duration
+        .with_label_values(&[route.as_str()])
+        .observe(started.elapsed().as_secs_f64());
+
+    response
+}
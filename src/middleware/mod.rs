@@ -1 +1,4 @@
-pub mod authorization;
\ No newline at end of file
+pub mod authorization;
+pub mod metrics;
+pub mod request_id;
+pub mod trace;
\ No newline at end of file
@@ -0,0 +1,119 @@
+//! Request ID middleware for the OpenAI Proxy Carousel
+//!
+//! Every request gets an ID: the client's `X-Request-Id` when it is well-formed, a new
+//! UUID otherwise. The ID is forwarded upstream, returned to the client, recorded in the
+//! activity log and added to the log lines written while the request is handled. The
+//! upstream's own `x-request-id` is returned as `x-upstream-request-id`.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::Body,
+    http::{HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use futures_util::Stream;
+
+/// Header carrying the request ID, both ways
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+/// Header the upstream's request ID is returned in
+pub const UPSTREAM_REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-upstream-request-id");
+
+/// Client IDs longer than this are replaced
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+/// The ID of a request, stored in the request extensions
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// Returns the ID of the request handled by the current task, if any
+pub fn current() -> Option<String> {
+    CURRENT.try_with(|id| id.0.clone()).ok()
+}
+
+/// A stream polled and dropped with its request ID set, so that log lines written
+/// while a response body is streamed carry the ID
+pub struct Scoped<S> {
+    id: RequestId,
+    stream: Option<Pin<Box<S>>>,
+}
+
+impl<S: Stream> Scoped<S> {
+    pub fn new(id: RequestId, stream: S) -> Self {
+        Self { id, stream: Some(Box::pin(stream)) }
+    }
+}
+
+impl<S: Stream> Stream for Scoped<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let Some(stream) = this.stream.as_mut() else {
+            return Poll::Ready(None);
+        };
+
+        CURRENT.sync_scope(this.id.clone(), || stream.as_mut().poll_next(cx))
+    }
+}
+
+impl<S> Drop for Scoped<S> {
+    fn drop(&mut self) {
+        // A client disconnecting mid-stream drops the stream outside of any request
+        if let Some(stream) = self.stream.take() {
+            CURRENT.sync_scope(self.id.clone(), || drop(stream));
+        }
+    }
+}
+
+/// Accepts IDs that are safe to log and to forward as a header
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LENGTH
+        && id.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"-_.:".contains(&byte))
+}
+
+/// Assigns the request ID and returns it in the response
+pub async fn request_id_middleware(mut request: Request<Body>, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    // Only valid IDs or UUIDs get here, both are valid header values
+    let value = HeaderValue::from_str(&id).unwrap();
+
+    request.headers_mut().insert(REQUEST_ID_HEADER, value.clone());
+    request.extensions_mut().insert(RequestId(id.clone()));
+
+    let mut response = CURRENT.scope(RequestId(id), next.run(request)).await;
+    response.headers_mut().insert(REQUEST_ID_HEADER, value);
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn scoped_streams_see_the_request_id() {
+        let stream = futures_util::stream::iter(0..2).map(|_| current());
+        let ids: Vec<_> = Scoped::new(RequestId("req-1".to_string()), stream).collect().await;
+
+        assert_eq!(ids, [Some("req-1".to_string()), Some("req-1".to_string())]);
+        assert_eq!(current(), None);
+    }
+}
@@ -0,0 +1,33 @@
+//! Request tracing middleware for the OpenAI Proxy Carousel
+//!
+//! Opens the root span of every request, continuing the client's trace when the
+//! request carries a W3C `traceparent` header. The spans are exported when
+//! `[telemetry]` is configured and cost next to nothing otherwise.
+
+use axum::{body::Body, http::Request, middleware::Next, response::Response};
+use tracing::Instrument;
+
+use super::metrics::route_label;
+use super::request_id::RequestId;
+use crate::telemetry;
+
+pub async fn trace_middleware(request: Request<Body>, next: Next) -> Response {
+    let method = request.method().clone();
+    let route = route_label(&request);
+    let request_id = request.extensions().get::<RequestId>().map(|id| id.0.clone()).unwrap_or_default();
+    let span = tracing::info_span!(
+        "request",
+        otel.name = %format!("{} {}", method, route),
+        otel.kind = "server",
+        http.request.method = %method,
+        http.route = %route,
+        request_id = %request_id,
+        http.response.status_code = tracing::field::Empty,
+    );
+    telemetry::set_parent_from(&span, request.headers());
+
+    let response = next.run(request).instrument(span.clone()).await;
+    span.record("http.response.status_code", response.status().as_u16());
+
+    response
+}
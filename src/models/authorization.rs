@@ -5,40 +5,75 @@
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
-    Json,
 };
+use serde::Serialize;
+
+use super::ProxyError;
 
 /// Custom error type for authorization failures
 #[derive(Debug)]
 pub enum AuthorizationError {
-    /// Returned when no Authorization header is present in the request
+    /// Returned when none of the configured credential sources is present in the request
     MissingAuthorizationHeader,
     /// Returned when the Authorization header doesn't use the Bearer scheme
     InvalidAuthorizationScheme,
     /// Returned when the provided access key is not in the configured access_keys list
     Unauthorized,
+    /// Returned for `/admin` requests when no admin keys are configured
+    AdminApiDisabled,
+    /// Returned when a JWT bearer token fails verification
+    InvalidToken,
+    /// Returned when the client is authenticated but not allowed to use the proxy
+    Forbidden,
+    /// Returned when the access key is past its `expires_at`
+    AccessKeyExpired,
+    /// Returned when the access key is used before its `not_before`
+    AccessKeyNotYetValid,
+    /// Returned when the access key has been disabled
+    AccessKeyDisabled,
+}
+
+impl AuthorizationError {
+    /// Stable, machine-readable code of the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuthorizationError::MissingAuthorizationHeader => "missing_credentials",
+            AuthorizationError::InvalidAuthorizationScheme => "invalid_authorization_scheme",
+            AuthorizationError::Unauthorized => "invalid_access_key",
+            AuthorizationError::AdminApiDisabled => "admin_api_disabled",
+            AuthorizationError::InvalidToken => "invalid_token",
+            AuthorizationError::Forbidden => "forbidden",
+            AuthorizationError::AccessKeyExpired => "access_key_expired",
+            AuthorizationError::AccessKeyNotYetValid => "access_key_not_yet_valid",
+            AuthorizationError::AccessKeyDisabled => "access_key_disabled",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AuthorizationError::AdminApiDisabled | AuthorizationError::Forbidden => StatusCode::FORBIDDEN,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            AuthorizationError::MissingAuthorizationHeader => "Authorization header or API key is missing",
+            AuthorizationError::InvalidAuthorizationScheme => "Invalid authorization scheme",
+            AuthorizationError::Unauthorized => "Access key is invalid or missing",
+            AuthorizationError::AdminApiDisabled => "Admin API is disabled",
+            AuthorizationError::InvalidToken => "Bearer token is invalid or expired",
+            AuthorizationError::Forbidden => "Client is not allowed to use the proxy",
+            AuthorizationError::AccessKeyExpired => "Access key has expired",
+            AuthorizationError::AccessKeyNotYetValid => "Access key is not valid yet",
+            AuthorizationError::AccessKeyDisabled => "Access key has been disabled",
+        }
+    }
 }
 
 impl IntoResponse for AuthorizationError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            AuthorizationError::MissingAuthorizationHeader => {
-                (StatusCode::UNAUTHORIZED, "Authorization header is missing")
-            }
-            AuthorizationError::InvalidAuthorizationScheme => {
-                (StatusCode::UNAUTHORIZED, "Invalid authorization scheme")
-            }
-            AuthorizationError::Unauthorized => {
-                (StatusCode::UNAUTHORIZED, "Access key is invalid or missing")
-            }
-        };
-
-        let body = Json(serde_json::json!({
-            "error": "Unauthorized",
-            "message": error_message,
-        }));
-
-        (status, body).into_response()
+        ProxyError::from(self).into_response()
     }
 }
 
@@ -47,4 +82,45 @@ impl IntoResponse for AuthorizationError {
 pub struct ValidatedAccessKey {
     /// The validated access key string
     pub key: String,
+    /// Owner of the key, from its configuration entry
+    pub owner: Option<String>,
+}
+
+impl ValidatedAccessKey {
+    /// Identifier of the access key that is safe to store and log
+    pub fn id(&self) -> String {
+        crate::key_manager::fingerprint(&self.key)
+    }
+}
+
+/// How a client authenticated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdentityKind {
+    AccessKey,
+    Jwt,
+    Certificate,
+}
+
+/// The authenticated client of a request, whatever the credential it presented
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    /// Identifier used to attribute usage: the key fingerprint or the JWT `sub` claim
+    pub id: String,
+    pub kind: IdentityKind,
+    /// Groups of the client, taken from the JWT groups claim
+    pub groups: Vec<String>,
+    /// Owner of the access key, when configured
+    pub owner: Option<String>,
+}
+
+impl From<ValidatedAccessKey> for ClientIdentity {
+    fn from(key: ValidatedAccessKey) -> Self {
+        Self {
+            id: key.id(),
+            kind: IdentityKind::AccessKey,
+            groups: Vec::new(),
+            owner: key.owner,
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,298 @@
+//! Streamed chat completion models for the OpenAI Proxy Carousel
+//!
+//! This module reassembles a `text/event-stream` chat completion into the equivalent
+//! non-streaming response, so that streams can be logged like any other response.
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Map, Value};
+
+use crate::models::usage::TokenUsage;
+
+#[derive(Debug, Default)]
+struct ToolCallAccumulator {
+    id: Option<String>,
+    kind: Option<String>,
+    name: String,
+    arguments: String,
+}
+
+#[derive(Debug, Default)]
+struct ChoiceAccumulator {
+    role: Option<String>,
+    content: Option<String>,
+    refusal: Option<String>,
+    finish_reason: Option<String>,
+    tool_calls: BTreeMap<u64, ToolCallAccumulator>,
+}
+
+/// Incrementally parses the chunks of a streamed chat completion
+///
+/// The upstream only reports usage for streams when the client asks for it with
+/// `stream_options.include_usage`, in which case it arrives in the last chunk.
+#[derive(Debug, Default)]
+pub struct StreamAccumulator {
+    pending: Vec<u8>,
+    id: Option<String>,
+    model: Option<String>,
+    created: Option<i64>,
+    choices: BTreeMap<u64, ChoiceAccumulator>,
+    usage: Option<TokenUsage>,
+}
+
+impl StreamAccumulator {
+    /// Feeds the next chunk of the stream, which may end in the middle of an event
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.pending.extend_from_slice(chunk);
+
+        while let Some(position) = self.pending.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=position).collect();
+            self.parse_line(&line);
+        }
+    }
+
+    /// Returns the usage reported by the stream, if any
+    pub fn usage(&self) -> Option<TokenUsage> {
+        self.usage
+    }
+
+    /// Builds the `chat.completion` object equivalent to the chunks seen so far
+    pub fn to_completion(&self) -> Value {
+        let choices: Vec<Value> = self
+            .choices
+            .iter()
+            .map(|(index, choice)| {
+                let mut message = Map::new();
+                message.insert(
+                    "role".to_string(),
+                    json!(choice.role.as_deref().unwrap_or("assistant")),
+                );
+                message.insert("content".to_string(), json!(choice.content));
+                if let Some(refusal) = &choice.refusal {
+                    message.insert("refusal".to_string(), json!(refusal));
+                }
+                if !choice.tool_calls.is_empty() {
+                    let tool_calls: Vec<Value> = choice
+                        .tool_calls
+                        .values()
+                        .map(|call| {
+                            json!({
+                                "id": call.id,
+                                "type": call.kind.as_deref().unwrap_or("function"),
+                                "function": {
+                                    "name": call.name,
+                                    "arguments": call.arguments,
+                                },
+                            })
+                        })
+                        .collect();
+                    message.insert("tool_calls".to_string(), Value::Array(tool_calls));
+                }
+
+                json!({
+                    "index": index,
+                    "message": message,
+                    "finish_reason": choice.finish_reason,
+                })
+            })
+            .collect();
+
+        json!({
+            "id": self.id,
+            "object": "chat.completion",
+            "created": self.created,
+            "model": self.model,
+            "choices": choices,
+            "usage": self.usage,
+        })
+    }
+
+    fn parse_line(&mut self, line: &[u8]) {
+        let Ok(line) = std::str::from_utf8(line) else {
+            return;
+        };
+        let Some(data) = line.trim().strip_prefix("data:") else {
+            return;
+        };
+        let data = data.trim();
+        if data.is_empty() || data == "[DONE]" {
+            return;
+        }
+
+        if let Ok(chunk) = serde_json::from_str::<Value>(data) {
+            self.apply_chunk(&chunk);
+        }
+    }
+
+    fn apply_chunk(&mut self, chunk: &Value) {
+        if let Some(usage) = TokenUsage::from_json(chunk) {
+            self.usage = Some(usage);
+        }
+        if self.id.is_none() {
+            self.id = chunk.get("id").and_then(Value::as_str).map(str::to_string);
+        }
+        if self.model.is_none() {
+            self.model = chunk.get("model").and_then(Value::as_str).map(str::to_string);
+        }
+        if self.created.is_none() {
+            self.created = chunk.get("created").and_then(Value::as_i64);
+        }
+
+        let Some(choices) = chunk.get("choices").and_then(Value::as_array) else {
+            return;
+        };
+        for choice in choices {
+            let index = choice.get("index").and_then(Value::as_u64).unwrap_or(0);
+            let accumulator = self.choices.entry(index).or_default();
+
+            if let Some(reason) = choice.get("finish_reason").and_then(Value::as_str) {
+                accumulator.finish_reason = Some(reason.to_string());
+            }
+
+            let Some(delta) = choice.get("delta") else {
+                continue;
+            };
+            if let Some(role) = delta.get("role").and_then(Value::as_str) {
+                accumulator.role = Some(role.to_string());
+            }
+            if let Some(content) = delta.get("content").and_then(Value::as_str) {
+                accumulator.content.get_or_insert_with(String::new).push_str(content);
+            }
+            if let Some(refusal) = delta.get("refusal").and_then(Value::as_str) {
+                accumulator.refusal.get_or_insert_with(String::new).push_str(refusal);
+            }
+            for call in delta.get("tool_calls").and_then(Value::as_array).into_iter().flatten() {
+                let call_index = call.get("index").and_then(Value::as_u64).unwrap_or(0);
+                let tool_call = accumulator.tool_calls.entry(call_index).or_default();
+
+                if let Some(id) = call.get("id").and_then(Value::as_str) {
+                    tool_call.id = Some(id.to_string());
+                }
+                if let Some(kind) = call.get("type").and_then(Value::as_str) {
+                    tool_call.kind = Some(kind.to_string());
+                }
+                if let Some(function) = call.get("function") {
+                    if let Some(name) = function.get("name").and_then(Value::as_str) {
+                        tool_call.name.push_str(name);
+                    }
+                    if let Some(arguments) = function.get("arguments").and_then(Value::as_str) {
+                        tool_call.arguments.push_str(arguments);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `stream` to an accumulator in chunks of `size` bytes, splitting lines and
+    /// multi-byte characters
+    fn accumulate(stream: &str, size: usize) -> StreamAccumulator {
+        let mut accumulator = StreamAccumulator::default();
+        for chunk in stream.as_bytes().chunks(size) {
+            accumulator.push(chunk);
+        }
+        accumulator
+    }
+
+    fn event(chunk: Value) -> String {
+        format!("data: {}\n\n", chunk)
+    }
+
+    #[test]
+    fn reassembles_split_chunks_of_several_choices() {
+        let delta = |index: u64, delta: Value, finish_reason: Option<&str>| {
+            event(json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion.chunk",
+                "created": 1700000000,
+                "model": "gpt-4o",
+                "choices": [{ "index": index, "delta": delta, "finish_reason": finish_reason }],
+            }))
+        };
+        let stream = [
+            delta(0, json!({ "role": "assistant", "content": "" }), None),
+            delta(1, json!({ "role": "assistant", "content": "" }), None),
+            delta(0, json!({ "content": "Grüße, " }), None),
+            delta(1, json!({ "content": "Hello" }), None),
+            delta(0, json!({ "content": "Welt" }), None),
+            delta(0, json!({}), Some("stop")),
+            delta(1, json!({}), Some("length")),
+            "data: [DONE]\n\n".to_string(),
+        ]
+        .concat();
+
+        for size in [1, 7, stream.len()] {
+            let completion = accumulate(&stream, size).to_completion();
+
+            assert_eq!(completion["id"], "chatcmpl-1");
+            assert_eq!(completion["object"], "chat.completion");
+            assert_eq!(completion["created"], 1700000000);
+            assert_eq!(completion["model"], "gpt-4o");
+            assert_eq!(completion["choices"][0]["message"], json!({ "role": "assistant", "content": "Grüße, Welt" }));
+            assert_eq!(completion["choices"][0]["finish_reason"], "stop");
+            assert_eq!(completion["choices"][1]["message"]["content"], "Hello");
+            assert_eq!(completion["choices"][1]["finish_reason"], "length");
+            assert_eq!(completion["usage"], Value::Null);
+        }
+    }
+
+    #[test]
+    fn concatenates_tool_call_arguments() {
+        let call = |call: Value| {
+            event(json!({ "choices": [{ "index": 0, "delta": { "tool_calls": [call] }, "finish_reason": null }] }))
+        };
+        let stream = [
+            call(json!({ "index": 0, "id": "call_1", "type": "function", "function": { "name": "get_weather", "arguments": "" } })),
+            call(json!({ "index": 1, "id": "call_2", "type": "function", "function": { "name": "get_time", "arguments": "{}" } })),
+            call(json!({ "index": 0, "function": { "arguments": "{\"city\":" } })),
+            call(json!({ "index": 0, "function": { "arguments": "\"Paris\"}" } })),
+            event(json!({ "choices": [{ "index": 0, "delta": {}, "finish_reason": "tool_calls" }] })),
+        ]
+        .concat();
+
+        let completion = accumulate(&stream, 5).to_completion();
+
+        let message = &completion["choices"][0]["message"];
+        assert_eq!(message["content"], Value::Null);
+        assert_eq!(
+            message["tool_calls"],
+            json!([
+                { "id": "call_1", "type": "function", "function": { "name": "get_weather", "arguments": "{\"city\":\"Paris\"}" } },
+                { "id": "call_2", "type": "function", "function": { "name": "get_time", "arguments": "{}" } },
+            ])
+        );
+        assert_eq!(completion["choices"][0]["finish_reason"], "tool_calls");
+    }
+
+    #[test]
+    fn reads_usage_from_the_last_chunk() {
+        let stream = [
+            event(json!({ "choices": [{ "index": 0, "delta": { "content": "Hi" } }], "usage": null })),
+            event(json!({ "choices": [], "usage": { "prompt_tokens": 12, "completion_tokens": 3, "total_tokens": 15 } })),
+            "data: [DONE]\n\n".to_string(),
+        ]
+        .concat();
+
+        let accumulator = accumulate(&stream, 3);
+
+        let usage = accumulator.usage().unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.total_tokens), (12, 3, 15));
+        assert_eq!(accumulator.to_completion()["usage"]["total_tokens"], 15);
+        assert_eq!(accumulator.to_completion()["choices"][0]["message"]["content"], "Hi");
+    }
+
+    #[test]
+    fn ignores_comments_and_incomplete_lines() {
+        let mut accumulator = StreamAccumulator::default();
+        accumulator.push(b": keep-alive\n\n");
+        accumulator.push(b"data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"}}]}\r\n\r\n");
+        accumulator.push(b"data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\" there\"");
+
+        assert_eq!(accumulator.to_completion()["choices"][0]["message"]["content"], "Hi");
+        assert!(accumulator.usage().is_none());
+    }
+}
@@ -1,7 +1,9 @@
-use std::{env, sync::LazyLock};
-use figment::{Figment, providers::{Format, Toml, Json, Env}};
+use std::{collections::HashMap, env, net::IpAddr, path::PathBuf, sync::OnceLock};
+use figment::{Figment, providers::{Format, Toml, Json, Env, Serialized}};
+use jsonwebtoken::Algorithm;
 use log::LevelFilter;
-use serde::Deserialize;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Deserializer};
 
 // use crate::pkg::figment_string;
 
@@ -98,6 +100,394 @@ use serde::Deserialize;
 //     }
 // }
 
+/// Price of a model in USD per one million tokens
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct ModelPricing {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+impl ModelPricing {
+    pub fn cost(&self, prompt_tokens: u64, completion_tokens: u64) -> f64 {
+        (prompt_tokens as f64 * self.prompt + completion_tokens as f64 * self.completion)
+            / 1_000_000.0
+    }
+}
+
+/// Built-in patterns for personal data that can be masked in logged payloads
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactionPreset {
+    Email,
+    Phone,
+    CardNumber,
+}
+
+/// Masking applied to payloads before they are logged
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct RedactionConfig {
+    /// Built-in patterns to mask
+    #[serde(default)]
+    pub presets: Vec<RedactionPreset>,
+    /// Additional regular expressions whose matches are masked
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// JSON paths (e.g. `$.messages[*].content`) whose values are masked entirely
+    #[serde(default)]
+    pub json_paths: Vec<String>,
+    /// Text that replaces masked values, `[REDACTED]` by default
+    pub replacement: Option<String>,
+}
+
+/// How log lines are written
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// `<time> <level> <module> - [<request id>] <message>`
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// When the log file is rotated
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogRotation {
+    #[default]
+    Never,
+    Minutely,
+    Hourly,
+    Daily,
+}
+
+fn default_log_file() -> Option<String> {
+    Some("log/data.log".to_string())
+}
+
+/// Log output settings; the level is set by `log_level`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Log file written next to stdout
+    #[serde(default = "default_log_file")]
+    pub file: Option<String>,
+    /// Only write to stdout, e.g. in containers
+    #[serde(default)]
+    pub stdout_only: bool,
+    #[serde(default)]
+    pub rotation: LogRotation,
+    /// Number of rotated files to keep, all when absent
+    pub max_files: Option<usize>,
+    /// Levels per module overriding `log_level`, e.g. `{ "openai_proxy::handlers" = "Debug" }`
+    #[serde(default)]
+    pub levels: HashMap<String, LevelFilter>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::default(),
+            file: default_log_file(),
+            stdout_only: false,
+            rotation: LogRotation::default(),
+            max_files: None,
+            levels: HashMap::new(),
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// The log file, unless only stdout is written
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref().filter(|_| !self.stdout_only)
+    }
+}
+
+/// Export of request traces to an OpenTelemetry collector
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TelemetryConfig {
+    /// OTLP/HTTP traces endpoint, e.g. `http://localhost:4318/v1/traces`
+    pub otlp_endpoint: String,
+    /// `service.name` of the exported spans, `openai-proxy` by default
+    pub service_name: Option<String>,
+    /// Fraction of traces started by the proxy that are sampled, 1.0 by default;
+    /// traces started by the client follow the client's sampling decision
+    pub sample_ratio: Option<f64>,
+}
+
+/// How client requests are authorized
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMode {
+    /// Every request must present a valid access key
+    #[default]
+    Required,
+    /// Requests without credentials are let through anonymously, invalid credentials are rejected
+    Optional,
+    /// No credentials are checked
+    Disabled,
+}
+
+fn default_jwt_algorithms() -> Vec<Algorithm> {
+    vec![Algorithm::RS256, Algorithm::ES256]
+}
+
+fn default_groups_claim() -> String {
+    "groups".to_string()
+}
+
+/// Verification of JWT bearer tokens issued by an identity provider
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct JwtConfig {
+    /// Path of a local JWKS document
+    pub jwks_file: Option<String>,
+    /// URL of the identity provider's JWKS document, used when `jwks_file` is not set
+    pub jwks_url: Option<String>,
+    /// Seconds after which the JWKS is reloaded, one hour by default
+    pub jwks_refresh_secs: Option<u64>,
+    /// Expected `iss` claim
+    pub issuer: String,
+    /// Expected `aud` claim
+    pub audience: String,
+    /// Accepted signature algorithms
+    #[serde(default = "default_jwt_algorithms")]
+    pub algorithms: Vec<Algorithm>,
+    /// Claim holding the groups of the client
+    #[serde(default = "default_groups_claim")]
+    pub groups_claim: String,
+    /// When not empty, only tokens with at least one of these groups are accepted
+    #[serde(default)]
+    pub allowed_groups: Vec<String>,
+}
+
+/// Where a client credential can be read from
+///
+/// Written in the configuration as `"authorization"` (Bearer scheme), `"x-api-key"`,
+/// `"api-key"`, `"header:<name>"` or `"query:<name>"`.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum CredentialSource {
+    /// `Authorization: Bearer <key>`
+    Authorization,
+    /// The raw value of a header, such as `x-api-key`
+    Header(String),
+    /// A query string parameter, such as `?key=`
+    Query(String),
+}
+
+impl TryFrom<String> for CredentialSource {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value.eq_ignore_ascii_case("authorization") {
+            return Ok(CredentialSource::Authorization);
+        }
+        if value.eq_ignore_ascii_case("x-api-key") || value.eq_ignore_ascii_case("api-key") {
+            return Ok(CredentialSource::Header(value.to_ascii_lowercase()));
+        }
+        if let Some(name) = value.strip_prefix("header:").filter(|name| !name.is_empty()) {
+            return Ok(CredentialSource::Header(name.to_ascii_lowercase()));
+        }
+        if let Some(name) = value.strip_prefix("query:").filter(|name| !name.is_empty()) {
+            return Ok(CredentialSource::Query(name.to_string()));
+        }
+
+        Err(format!(
+            "unknown credential source `{}`, expected \"authorization\", \"x-api-key\", \
+             \"api-key\", \"header:<name>\" or \"query:<name>\"",
+            value
+        ))
+    }
+}
+
+fn default_credential_sources() -> Vec<CredentialSource> {
+    vec![CredentialSource::Authorization]
+}
+
+/// Client authorization settings
+#[derive(Deserialize, Debug, Clone)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub mode: AuthMode,
+    /// Let requests from loopback peers omit credentials; presented ones are still checked
+    #[serde(default)]
+    pub exempt_loopback: bool,
+    /// Allow `mode = "disabled"` on a non-loopback listen address
+    #[serde(default)]
+    pub allow_disabled_on_public_address: bool,
+    /// Accept JWT bearer tokens in addition to access keys
+    pub jwt: Option<JwtConfig>,
+    /// Where client credentials are looked for, in order; all of them are stripped
+    /// before the request is forwarded upstream
+    #[serde(default = "default_credential_sources")]
+    pub credential_sources: Vec<CredentialSource>,
+    /// A warning is logged daily for keys expiring within this many days
+    #[serde(default = "default_expiry_warning_days")]
+    pub expiry_warning_days: u32,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            mode: AuthMode::default(),
+            exempt_loopback: false,
+            allow_disabled_on_public_address: false,
+            jwt: None,
+            credential_sources: default_credential_sources(),
+            expiry_warning_days: default_expiry_warning_days(),
+        }
+    }
+}
+
+/// Returns whether `host` only accepts connections from the local machine
+pub fn is_loopback_host(host: &str) -> bool {
+    if host.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .is_ok_and(|address| address.is_loopback())
+}
+
+/// Parses an RFC 3339 timestamp or a date (midnight UTC)
+pub fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Some(timestamp.with_timezone(&Utc));
+    }
+
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|datetime| datetime.and_utc())
+}
+
+/// A timestamp as written in the configuration: a string, or a native TOML datetime
+/// which figment passes through as a single-entry table
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TimestampValue {
+    Text(String),
+    Toml {
+        #[serde(rename = "$__toml_private_datetime")]
+        value: String,
+    },
+}
+
+fn deserialize_timestamp<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<TimestampValue>::deserialize(deserializer)?.map(|value| match value {
+        TimestampValue::Text(value) | TimestampValue::Toml { value } => value,
+    });
+
+    match value {
+        Some(value) => parse_timestamp(&value)
+            .map(Some)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid timestamp `{}`", value))),
+        None => Ok(None),
+    }
+}
+
+/// An access key entry, written either as a plain string or as a table
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AccessKeyDefinition {
+    Key(String),
+    Detailed {
+        key: String,
+        #[serde(default, deserialize_with = "deserialize_timestamp")]
+        not_before: Option<DateTime<Utc>>,
+        #[serde(default, deserialize_with = "deserialize_timestamp")]
+        expires_at: Option<DateTime<Utc>>,
+        description: Option<String>,
+        owner: Option<String>,
+        #[serde(default)]
+        disabled: bool,
+    },
+}
+
+/// A key entry with its validity window, used for upstream API keys as well as
+/// client and admin access keys
+#[derive(Deserialize, Debug, Clone)]
+#[serde(from = "AccessKeyDefinition")]
+pub struct AccessKey {
+    /// The key itself, plaintext or a `sha256:` hash
+    pub key: String,
+    /// The key is rejected before this time
+    pub not_before: Option<DateTime<Utc>>,
+    /// The key is rejected from this time on
+    pub expires_at: Option<DateTime<Utc>>,
+    pub description: Option<String>,
+    pub owner: Option<String>,
+    /// Disabled keys are kept in the configuration but never used or accepted
+    pub disabled: bool,
+    /// The `file:` or `env:` reference the key was resolved from
+    pub source: Option<String>,
+}
+
+impl From<AccessKeyDefinition> for AccessKey {
+    fn from(definition: AccessKeyDefinition) -> Self {
+        match definition {
+            AccessKeyDefinition::Key(key) => AccessKey::new(key),
+            AccessKeyDefinition::Detailed { key, not_before, expires_at, description, owner, disabled } => {
+                AccessKey { key, not_before, expires_at, description, owner, disabled, source: None }
+            }
+        }
+    }
+}
+
+impl AccessKey {
+    pub fn new(key: String) -> Self {
+        AccessKey {
+            key,
+            not_before: None,
+            expires_at: None,
+            description: None,
+            owner: None,
+            disabled: false,
+            source: None,
+        }
+    }
+
+    /// Returns whether the key is enabled and within its validity window at `now`
+    pub fn is_usable(&self, now: DateTime<Utc>) -> bool {
+        !self.disabled
+            && self.not_before.is_none_or(|not_before| now >= not_before)
+            && self.expires_at.is_none_or(|expires_at| now < expires_at)
+    }
+
+    /// Returns whether the entry holds nothing but the key, so it can be written as a plain string
+    pub fn is_plain(&self) -> bool {
+        self.not_before.is_none()
+            && self.expires_at.is_none()
+            && self.description.is_none()
+            && self.owner.is_none()
+            && !self.disabled
+    }
+
+    /// Describes the key for log messages without revealing it
+    pub fn label(&self) -> String {
+        let mut label = match &self.description {
+            Some(description) => format!("\"{}\"", description),
+            None => "access key".to_string(),
+        };
+        if let Some(owner) = &self.owner {
+            label.push_str(&format!(" (owner: {})", owner));
+        }
+
+        label
+    }
+}
+
+fn default_expiry_warning_days() -> u32 {
+    14
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Config {
     pub version: String,
@@ -105,16 +495,55 @@ pub struct Config {
     pub port: u16,
 
     pub host: String,
+    /// Serve HTTPS instead of HTTP
+    pub tls: Option<TlsConfig>,
+    /// Addresses to listen on; `host`, `port` and `tls` are used when empty
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
     /// Base URL for the OpenAI API (e.g., https://api.openai.com/v1)
     pub base_url: String,
     /// API keys for authenticating with OpenAI
-    pub api_keys: Vec<String>,
+    pub api_keys: Vec<AccessKey>,
     /// Access keys for authenticating to Proxy
-    pub access_keys: Vec<String>,
+    pub access_keys: Vec<AccessKey>,
+    /// How access keys are enforced
+    #[serde(default)]
+    pub auth: AuthConfig,
 
     pub log_level: Option<LevelFilter>,
+    /// Log format, file and per-module levels
+    #[serde(default)]
+    pub logging: LoggingConfig,
     
     pub acivity_logging_path: Option<String>,
+    /// Size in bytes after which the activity log is rotated and compressed
+    pub activity_log_max_bytes: Option<u64>,
+    /// Number of compressed activity logs to keep
+    pub activity_log_max_files: Option<usize>,
+    /// Masking applied to request and response bodies before they are logged
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+    /// Path to the SQLite database where per-request usage is recorded
+    pub usage_db_path: Option<String>,
+    /// Access keys for the `/admin` endpoints; the admin API is disabled when empty
+    #[serde(default)]
+    pub admin_keys: Vec<AccessKey>,
+    /// Write key changes made through the admin API back to the configuration file
+    #[serde(default)]
+    pub persist_key_changes: bool,
+    /// Per-model prices used to compute the cost of a request
+    #[serde(default)]
+    pub pricing: HashMap<String, ModelPricing>,
+    /// Seconds to wait for a connection to the upstream API
+    pub upstream_connect_timeout_secs: Option<u64>,
+    /// Seconds the upstream API may stay silent, before the response or between chunks
+    pub upstream_timeout_secs: Option<u64>,
+    /// Seconds readiness fails after SIGTERM or SIGINT before connections are refused
+    pub shutdown_pre_stop_delay_secs: Option<u64>,
+    /// Seconds in-flight requests may take to finish once connections are refused
+    pub shutdown_drain_timeout_secs: Option<u64>,
+    /// Trace export, disabled when absent
+    pub telemetry: Option<TelemetryConfig>,
     // pub gemini: Gemini,
     // pub tts: TTS,
     // pub jobs: JobConfig,
@@ -127,40 +556,656 @@ impl Clone for Config
             version: self.version.clone(),
             api_keys: self.api_keys.clone(),
             access_keys: self.access_keys.clone(),
-            log_level: self.log_level.clone(),
-            port: self.port.clone(),
+            auth: self.auth.clone(),
+            log_level: self.log_level,
+            logging: self.logging.clone(),
+            port: self.port,
             host: self.host.clone(),
+            tls: self.tls.clone(),
+            listeners: self.listeners.clone(),
             base_url: self.base_url.clone(),
             acivity_logging_path: self.acivity_logging_path.clone(),
+            activity_log_max_bytes: self.activity_log_max_bytes,
+            activity_log_max_files: self.activity_log_max_files,
+            redaction: self.redaction.clone(),
+            usage_db_path: self.usage_db_path.clone(),
+            admin_keys: self.admin_keys.clone(),
+            persist_key_changes: self.persist_key_changes,
+            pricing: self.pricing.clone(),
+            upstream_connect_timeout_secs: self.upstream_connect_timeout_secs,
+            upstream_timeout_secs: self.upstream_timeout_secs,
+            shutdown_pre_stop_delay_secs: self.shutdown_pre_stop_delay_secs,
+            shutdown_drain_timeout_secs: self.shutdown_drain_timeout_secs,
+            telemetry: self.telemetry.clone(),
         }
     }
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        let file_env = env::var("ENV").ok();
-        let file_name = match file_env {
-            Some(file_sufix) => format!("config.{}", file_sufix),
-            None => "config".to_string(),
+/// Returns the configuration file name without extension, `config` or `config.<ENV>`
+fn file_name() -> String {
+    match env::var("ENV").ok() {
+        Some(file_sufix) => format!("config.{}", file_sufix),
+        None => "config".to_string(),
+    }
+}
+
+/// Settings given on the command line, layered over the files and the environment
+#[derive(Debug, Default, Clone)]
+pub struct ConfigOverrides {
+    /// Configuration file to read instead of `config.toml` / `config.json`
+    pub path: Option<PathBuf>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub log_level: Option<LevelFilter>,
+}
+
+static OVERRIDES: OnceLock<ConfigOverrides> = OnceLock::new();
+
+fn overrides() -> &'static ConfigOverrides {
+    OVERRIDES.get_or_init(ConfigOverrides::default)
+}
+
+/// Which part of a client certificate identifies the client
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CertificateIdentity {
+    /// The first DNS, URI or email subject alternative name
+    #[default]
+    San,
+    /// The common name of the subject
+    CommonName,
+}
+
+/// Authentication of clients by TLS client certificates
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ClientAuthConfig {
+    /// PEM bundle of the CAs that client certificates must chain to
+    pub ca_path: String,
+    /// Reject connections without a client certificate during the handshake
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub identity_from: CertificateIdentity,
+    /// Identities allowed to use the proxy; any certificate issued by the CA when empty
+    #[serde(default)]
+    pub allowed_identities: Vec<String>,
+    /// Groups of certificate clients, by identity
+    #[serde(default)]
+    pub groups: HashMap<String, Vec<String>>,
+}
+
+/// TLS termination on the listener
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain, leaf first
+    pub cert_path: String,
+    /// PEM file with the private key
+    pub key_path: String,
+    /// Offer HTTP/2 through ALPN
+    #[serde(default)]
+    pub http2: bool,
+    /// Verify client certificates and accept them instead of access keys
+    pub client_auth: Option<ClientAuthConfig>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// An address the proxy listens on, with its own TLS and authorization settings
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ListenerConfig {
+    /// Name of the listener in logs
+    pub name: Option<String>,
+    /// `host:port` to listen on
+    pub address: Option<String>,
+    /// Path of a Unix domain socket to listen on instead of `address`
+    pub unix_socket: Option<String>,
+    pub tls: Option<TlsConfig>,
+    /// Overrides `auth.mode` on this listener
+    pub auth_mode: Option<AuthMode>,
+    /// Serve the `/admin` endpoints on this listener
+    #[serde(default = "default_true")]
+    pub admin: bool,
+    /// Serve `/metrics` on this listener
+    #[serde(default = "default_true")]
+    pub metrics: bool,
+}
+
+impl ListenerConfig {
+    /// Describes the listener for log messages
+    pub fn label(&self) -> String {
+        let endpoint = match (&self.address, &self.unix_socket) {
+            (Some(address), _) => address.clone(),
+            (None, Some(path)) => format!("unix:{}", path),
+            (None, None) => "?".to_string(),
         };
-        let figment = Figment::new()
-        .merge(Toml::file(format!("{}.toml", file_name)))
-        .merge(Env::prefixed("PROXY_").split("_"))
-        .join(Json::file(format!("{}.json", file_name)));
-        
-        match figment.extract() {
-            Ok(config) => config,
-            Err(e) => {
-                log::error!("{}", e);
-                panic!("{}", e);
+
+        match &self.name {
+            Some(name) => format!("{} ({})", name, endpoint),
+            None => endpoint,
+        }
+    }
+
+    /// Returns the host part of `address`
+    fn host(&self) -> Option<&str> {
+        let address = self.address.as_deref()?;
+        Some(address.rsplit_once(':').map_or(address, |(host, _)| host))
+    }
+
+    /// Whether only the local host can connect, on a loopback address or a Unix socket
+    fn is_loopback(&self) -> bool {
+        self.host().is_none_or(is_loopback_host)
+    }
+}
+
+/// A problem found while validating the configuration
+#[derive(Debug, thiserror::Error)]
+pub enum ValidationError {
+    #[error("port: must not be 0")]
+    InvalidPort,
+    #[error("{0}: must not be 0")]
+    ZeroTimeout(&'static str),
+    #[error("base_url: `{url}` is not a valid http(s) URL: {reason}")]
+    InvalidBaseUrl { url: String, reason: String },
+    #[error("api_keys: at least one upstream API key is required")]
+    NoApiKeys,
+    #[error("{list}[{index}]: key must not be empty")]
+    EmptyKey { list: &'static str, index: usize },
+    #[error("{list}[{index}]: key must be usable in an HTTP header")]
+    InvalidKey { list: &'static str, index: usize },
+    #[error("{list}[{index}]: duplicate of {list}[{first}]")]
+    DuplicateKey { list: &'static str, index: usize, first: usize },
+    #[error("{list}[{index}]: not_before must be earlier than expires_at")]
+    InvalidWindow { list: &'static str, index: usize },
+    #[error("{0}")]
+    Auth(String),
+    #[error("listeners[{index}]: {message}")]
+    Listener { index: usize, message: String },
+    #[error("{list}[{index}]: {source}")]
+    Secret {
+        list: &'static str,
+        index: usize,
+        source: crate::secrets::SecretError,
+    },
+    #[error("tls: {0}")]
+    Tls(#[from] crate::tls::TlsError),
+    #[error("redaction: {0}")]
+    Redaction(#[from] crate::redaction::RedactionError),
+    #[error("telemetry: {0}")]
+    Telemetry(String),
+    #[error("logging: {0}")]
+    Logging(String),
+}
+
+/// Why the configuration could not be loaded
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("configuration file {0} does not exist")]
+    Missing(String),
+    #[error("failed to read the configuration: {0}")]
+    Load(Box<figment::Error>),
+    #[error("invalid configuration:{}", .0.iter().map(|error| format!("\n  - {}", error)).collect::<String>())]
+    Invalid(Vec<ValidationError>),
+}
+
+/// Replaces `file:` and `env:` references in a key list by the secrets they point to
+fn resolve_keys(list: &'static str, keys: &mut [AccessKey], errors: &mut Vec<ValidationError>) {
+    for (index, entry) in keys.iter_mut().enumerate() {
+        if !crate::secrets::is_reference(&entry.key) {
+            continue;
+        }
+
+        match crate::secrets::resolve(&entry.key) {
+            Ok(key) => entry.source = Some(std::mem::replace(&mut entry.key, key)),
+            Err(source) => errors.push(ValidationError::Secret { list, index, source }),
+        }
+    }
+}
+
+/// Whether `key` can be sent as `Authorization: Bearer <key>`
+pub fn is_header_safe(key: &str) -> bool {
+    reqwest::header::HeaderValue::from_str(&format!("Bearer {}", key)).is_ok()
+}
+
+/// Checks a key list for empty keys, keys that cannot be sent in a header, duplicates
+/// and inverted validity windows
+fn validate_keys(list: &'static str, keys: &[AccessKey], errors: &mut Vec<ValidationError>) {
+    for (index, entry) in keys.iter().enumerate() {
+        if entry.key.trim().is_empty() {
+            errors.push(ValidationError::EmptyKey { list, index });
+        } else if !is_header_safe(&entry.key) {
+            errors.push(ValidationError::InvalidKey { list, index });
+        } else if let Some(first) = keys[..index].iter().position(|other| other.key == entry.key) {
+            errors.push(ValidationError::DuplicateKey { list, index, first });
+        }
+
+        if let (Some(not_before), Some(expires_at)) = (entry.not_before, entry.expires_at) {
+            if not_before >= expires_at {
+                errors.push(ValidationError::InvalidWindow { list, index });
             }
         }
     }
 }
 
 impl Config {
-    pub fn instance() -> Config {
-        static STATIC_INSTANCE: LazyLock<Config> = LazyLock::new(Config::default);
-        STATIC_INSTANCE.clone()
+    /// Reads the configuration from the TOML and JSON files and the `PROXY_` environment
+    /// variables, then validates it
+    pub fn load() -> Result<Config, ConfigError> {
+        let overrides = overrides();
+        let figment = match &overrides.path {
+            Some(path) if !path.exists() => {
+                return Err(ConfigError::Missing(path.display().to_string()));
+            }
+            Some(path) if path.extension().is_some_and(|extension| extension == "json") => {
+                Figment::new()
+                .merge(Json::file(path))
+                .merge(Env::prefixed("PROXY_").split("_"))
+            }
+            Some(path) => {
+                Figment::new()
+                .merge(Toml::file(path))
+                .merge(Env::prefixed("PROXY_").split("_"))
+            }
+            None => {
+                let file_name = file_name();
+                Figment::new()
+                .merge(Toml::file(format!("{}.toml", file_name)))
+                .merge(Env::prefixed("PROXY_").split("_"))
+                .join(Json::file(format!("{}.json", file_name)))
+            }
+        };
+
+        let mut figment = figment;
+        if let Some(host) = &overrides.host {
+            figment = figment.merge(Serialized::default("host", host));
+        }
+        if let Some(port) = overrides.port {
+            figment = figment.merge(Serialized::default("port", port));
+        }
+        if let Some(log_level) = overrides.log_level {
+            figment = figment.merge(Serialized::default("log_level", log_level));
+        }
+
+        let mut config: Config = figment
+        .extract()
+        .map_err(|e| ConfigError::Load(Box::new(e)))?;
+
+        let mut errors = Vec::new();
+        resolve_keys("api_keys", &mut config.api_keys, &mut errors);
+        resolve_keys("access_keys", &mut config.access_keys, &mut errors);
+        resolve_keys("admin_keys", &mut config.admin_keys, &mut errors);
+        if let Err(invalid) = config.validate() {
+            errors.extend(invalid);
+        }
+
+        if errors.is_empty() { Ok(config) } else { Err(ConfigError::Invalid(errors)) }
+    }
+
+    /// Checks the whole configuration, reporting every problem rather than the first one
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+
+        if self.port == 0 {
+            errors.push(ValidationError::InvalidPort);
+        }
+        if self.upstream_connect_timeout_secs == Some(0) {
+            errors.push(ValidationError::ZeroTimeout("upstream_connect_timeout_secs"));
+        }
+        if self.upstream_timeout_secs == Some(0) {
+            errors.push(ValidationError::ZeroTimeout("upstream_timeout_secs"));
+        }
+        match reqwest::Url::parse(&self.base_url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+            Ok(url) => errors.push(ValidationError::InvalidBaseUrl {
+                url: self.base_url.clone(),
+                reason: format!("unsupported scheme `{}`", url.scheme()),
+            }),
+            Err(e) => errors.push(ValidationError::InvalidBaseUrl {
+                url: self.base_url.clone(),
+                reason: e.to_string(),
+            }),
+        }
+
+        if self.api_keys.is_empty() {
+            errors.push(ValidationError::NoApiKeys);
+        }
+        validate_keys("api_keys", &self.api_keys, &mut errors);
+        validate_keys("access_keys", &self.access_keys, &mut errors);
+        validate_keys("admin_keys", &self.admin_keys, &mut errors);
+
+        if let Err(auth_errors) = self.check_auth() {
+            errors.extend(auth_errors.into_iter().map(ValidationError::Auth));
+        }
+        self.validate_listeners(&mut errors);
+        if let Err(e) = crate::redaction::Redactor::new(self) {
+            errors.push(e.into());
+        }
+        if let Err(e) = crate::logger::build_filter(self.log_level, &self.logging) {
+            errors.push(ValidationError::Logging(e));
+        }
+        if let Some(telemetry) = &self.telemetry {
+            if let Err(e) = reqwest::Url::parse(&telemetry.otlp_endpoint) {
+                errors.push(ValidationError::Telemetry(format!(
+                    "invalid otlp_endpoint `{}`: {}",
+                    telemetry.otlp_endpoint, e
+                )));
+            }
+            if telemetry.sample_ratio.is_some_and(|ratio| !(0.0..=1.0).contains(&ratio)) {
+                errors.push(ValidationError::Telemetry("sample_ratio must be between 0 and 1".to_string()));
+            }
+        }
+        if self.listeners.is_empty() {
+            if let Some(tls) = &self.tls {
+                if let Err(e) = crate::tls::load_server_config(tls) {
+                    errors.push(e.into());
+                }
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Sets the command-line overrides; must be called before the configuration is loaded
+    pub fn set_overrides(config_overrides: ConfigOverrides) {
+        _ = OVERRIDES.set(config_overrides);
+    }
+
+    /// Files the configuration is read from, whether they exist or not
+    pub fn source_files() -> Vec<PathBuf> {
+        if let Some(path) = &overrides().path {
+            return vec![path.clone()];
+        }
+
+        let file_name = file_name();
+        vec![
+            PathBuf::from(format!("{}.toml", file_name)),
+            PathBuf::from(format!("{}.json", file_name)),
+        ]
+    }
+
+    /// Path of the TOML configuration file
+    pub fn file_path() -> PathBuf {
+        match &overrides().path {
+            Some(path) => path.clone(),
+            None => PathBuf::from(format!("{}.toml", file_name())),
+        }
+    }
+
+    /// The configured listeners, or the one described by `host`, `port` and `tls`
+    pub fn listeners(&self) -> Vec<ListenerConfig> {
+        if !self.listeners.is_empty() {
+            return self.listeners.clone();
+        }
+
+        vec![ListenerConfig {
+            name: None,
+            address: Some(format!("{}:{}", self.host, self.port)),
+            unix_socket: None,
+            tls: self.tls.clone(),
+            auth_mode: None,
+            admin: true,
+            metrics: true,
+        }]
+    }
+
+    /// Whether `auth.mode` asks for credentials on `listener` but none can be checked there
+    pub fn lacks_credentials(&self, listener: &ListenerConfig) -> bool {
+        let mode = listener.auth_mode.unwrap_or(self.auth.mode);
+        let client_auth = listener.tls.as_ref().and_then(|tls| tls.client_auth.as_ref());
+
+        mode != AuthMode::Disabled && self.access_keys.is_empty() && self.auth.jwt.is_none() && client_auth.is_none()
+    }
+
+    /// Describes the loopback listeners that are served without authentication because
+    /// no credentials are configured, as before `auth.mode` existed
+    pub fn auth_warnings(&self) -> Vec<String> {
+        self.listeners()
+            .iter()
+            .filter(|listener| listener.is_loopback() && self.lacks_credentials(listener))
+            .map(|listener| {
+                format!(
+                    "auth.mode requires credentials on {} but neither access_keys, auth.jwt nor tls.client_auth \
+                     are configured; every request is let through without authentication. Configure credentials, \
+                     or set auth.mode = \"disabled\" to keep this behavior and silence this warning",
+                    listener.label()
+                )
+            })
+            .collect()
+    }
+
+    /// Checks that the authorization settings are safe for every listener
+    pub fn check_auth(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        if let Some(jwt) = &self.auth.jwt {
+            if jwt.jwks_file.is_none() && jwt.jwks_url.is_none() {
+                errors.push("auth.jwt needs either jwks_file or jwks_url".to_string());
+            }
+        }
+
+        for listener in self.listeners() {
+            let mode = listener.auth_mode.unwrap_or(self.auth.mode);
+
+            match mode {
+                // Only the local host can reach a loopback listener, see `auth_warnings`
+                AuthMode::Required | AuthMode::Optional
+                    if self.lacks_credentials(&listener) && !listener.is_loopback() =>
+                {
+                    errors.push(format!(
+                        "auth.mode is \"{}\" on {} but neither access_keys, auth.jwt nor tls.client_auth are configured",
+                        if mode == AuthMode::Required { "required" } else { "optional" },
+                        listener.label()
+                    ));
+                }
+                // Unix sockets are only reachable from the host
+                AuthMode::Disabled
+                    if listener.host().is_some_and(|host| !is_loopback_host(host))
+                        && !self.auth.allow_disabled_on_public_address =>
+                {
+                    errors.push(format!(
+                        "auth.mode is \"disabled\" on non-loopback address {}; \
+                         set auth.allow_disabled_on_public_address = true to allow it",
+                        listener.label()
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    fn validate_listeners(&self, errors: &mut Vec<ValidationError>) {
+        for (index, listener) in self.listeners.iter().enumerate() {
+            let mut invalid = |message: String| errors.push(ValidationError::Listener { index, message });
+
+            match (&listener.address, &listener.unix_socket) {
+                (Some(_), Some(_)) => invalid("set either address or unix_socket, not both".to_string()),
+                (None, None) => invalid("address or unix_socket is required".to_string()),
+                (Some(address), None) => {
+                    let port = address.rsplit_once(':').and_then(|(_, port)| port.parse::<u16>().ok());
+                    if port.is_none() {
+                        invalid(format!("address `{}` must be host:port", address));
+                    }
+                }
+                (None, Some(_)) => {}
+            }
+            if let Some(tls) = &listener.tls {
+                if let Err(e) = crate::tls::load_server_config(tls) {
+                    invalid(format!("tls: {}", e));
+                }
+            }
+            if let Some(first) = self.listeners[..index]
+                .iter()
+                .position(|other| other.name.is_some() && other.name == listener.name)
+            {
+                invalid(format!("duplicate name, also used by listeners[{}]", first));
+            }
+        }
+    }
+
+    /// Looks up the price of a model, falling back to the longest configured prefix
+    /// so that dated snapshots (e.g. `gpt-4o-2024-08-06`) resolve to `gpt-4o`; the name
+    /// of the matching entry is returned with it
+    pub fn pricing_for(&self, model: &str) -> Option<(&str, ModelPricing)> {
+        if let Some((name, pricing)) = self.pricing.get_key_value(model) {
+            return Some((name.as_str(), *pricing));
+        }
+
+        self.pricing
+            .iter()
+            .filter(|(name, _)| model.starts_with(name.as_str()))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(name, pricing)| (name.as_str(), *pricing))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `toml` and returns the messages `validate` reports for it
+    fn validation_errors(toml: &str) -> Vec<String> {
+        let config: Config = Figment::from(Toml::string(toml)).extract().unwrap();
+        match config.validate() {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn accepts_a_minimal_configuration() {
+        let errors = validation_errors(
+            r#"
+            version = "test"
+            host = "127.0.0.1"
+            port = 8080
+            base_url = "https://api.openai.com/v1"
+            api_keys = ["sk-a"]
+            access_keys = ["ak-1"]
+            "#,
+        );
+
+        assert_eq!(errors, Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let errors = validation_errors(
+            r#"
+            version = "test"
+            host = "127.0.0.1"
+            port = 0
+            base_url = "https://api.openai.com/v1"
+            api_keys = ["sk-a", "sk-b\nx"]
+            access_keys = [
+                "ak-1",
+                "ak-1",
+                { key = "ak-2", not_before = "2026-02-01T00:00:00Z", expires_at = "2026-01-01T00:00:00Z" },
+            ]
+
+            [auth.jwt]
+            issuer = "https://issuer.example"
+            audience = "openai-proxy"
+            "#,
+        );
+
+        assert_eq!(
+            errors,
+            [
+                "port: must not be 0",
+                "api_keys[1]: key must be usable in an HTTP header",
+                "access_keys[1]: duplicate of access_keys[0]",
+                "access_keys[2]: not_before must be earlier than expires_at",
+                "auth.jwt needs either jwks_file or jwks_url",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_public_listeners_without_credentials() {
+        let errors = validation_errors(
+            r#"
+            version = "test"
+            host = "0.0.0.0"
+            port = 8080
+            base_url = "https://api.openai.com/v1"
+            api_keys = ["sk-a"]
+            access_keys = []
+            auth = { mode = "required" }
+            "#,
+        );
+
+        assert_eq!(
+            errors,
+            ["auth.mode is \"required\" on 0.0.0.0:8080 but neither access_keys, auth.jwt nor tls.client_auth are configured"]
+        );
+    }
+
+    #[test]
+    fn rejects_disabled_auth_on_public_listeners() {
+        let errors = validation_errors(
+            r#"
+            version = "test"
+            host = "0.0.0.0"
+            port = 8080
+            base_url = "https://api.openai.com/v1"
+            api_keys = ["sk-a"]
+            access_keys = []
+            auth = { mode = "disabled" }
+            "#,
+        );
+
+        assert_eq!(
+            errors,
+            [
+                "auth.mode is \"disabled\" on non-loopback address 0.0.0.0:8080; \
+                 set auth.allow_disabled_on_public_address = true to allow it"
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_pricing_entries_by_longest_prefix() {
+        let config: Config = Figment::from(Toml::string(
+            r#"
+            version = "test"
+            host = "127.0.0.1"
+            port = 8080
+            base_url = "https://api.openai.com/v1"
+            api_keys = ["sk-a"]
+            access_keys = []
+
+            [pricing]
+            "gpt-4o" = { prompt = 2.5, completion = 10.0 }
+            "gpt-4o-mini" = { prompt = 0.15, completion = 0.6 }
+            "#,
+        ))
+        .extract()
+        .unwrap();
+        let entry = |model| config.pricing_for(model).map(|(name, pricing)| (name, pricing.prompt));
+
+        assert_eq!(entry("gpt-4o"), Some(("gpt-4o", 2.5)));
+        assert_eq!(entry("gpt-4o-2024-08-06"), Some(("gpt-4o", 2.5)));
+        assert_eq!(entry("gpt-4o-mini-2024-07-18"), Some(("gpt-4o-mini", 0.15)));
+        assert_eq!(entry("o1-preview"), None);
+    }
+
+    #[test]
+    fn reports_secrets_spanning_several_lines() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("keys");
+        std::fs::write(&path, "sk-first\nsk-second\n").unwrap();
+        let reference = format!("file:{}", path.display());
+        let mut keys = vec![AccessKey::from(AccessKeyDefinition::Key(reference.clone()))];
+
+        let mut errors = Vec::new();
+        resolve_keys("api_keys", &mut keys, &mut errors);
+
+        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            errors,
+            [format!("api_keys[0]: secret `{reference}` contains control characters, such as a line break")]
+        );
     }
 }
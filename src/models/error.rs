@@ -0,0 +1,154 @@
+//! Error responses for the OpenAI Proxy Carousel
+//!
+//! Every error the proxy generates itself, as opposed to errors relayed from the
+//! upstream, is a [`ProxyError`]. It is rendered in the OpenAI error format,
+//! `{"error": {"message", "type", "code", "param"}}`, so that the official SDKs can
+//! parse it. The `code` values are stable and safe to match on.
+
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use super::AuthorizationError;
+
+/// An error generated by the proxy
+#[derive(Debug, thiserror::Error)]
+pub enum ProxyError {
+    #[error("{}", .0.message())]
+    Authorization(AuthorizationError),
+    /// The request is malformed; `param` names the offending parameter
+    #[error("{message}")]
+    InvalidRequest { message: String, param: Option<&'static str> },
+    /// No route matches the path
+    #[error("Unknown request URL: {0}")]
+    UnknownUrl(String),
+    /// The path exists but does not accept the method
+    #[error("Method {method} is not allowed for {path}")]
+    MethodNotAllowed { method: String, path: String },
+    #[error("{message}")]
+    NotFound { code: &'static str, message: String },
+    #[error("{message}")]
+    Conflict { code: &'static str, message: String },
+    /// Every upstream key is disabled or outside its validity window
+    #[error("No upstream API key available")]
+    NoUpstreamKey,
+    #[error("The upstream API could not be reached: {0}")]
+    UpstreamUnavailable(String),
+    #[error("The upstream API did not respond in time")]
+    UpstreamTimeout,
+    /// The upstream connection failed after a streamed response had started
+    #[error("The upstream response was interrupted: {0}")]
+    UpstreamInterrupted(String),
+    #[error("{message}")]
+    Internal { code: &'static str, message: String },
+}
+
+impl ProxyError {
+    pub fn invalid_param(param: &'static str, message: impl Into<String>) -> Self {
+        ProxyError::InvalidRequest { message: message.into(), param: Some(param) }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ProxyError::Authorization(e) => e.status(),
+            ProxyError::InvalidRequest { .. } => StatusCode::BAD_REQUEST,
+            ProxyError::UnknownUrl(_) | ProxyError::NotFound { .. } => StatusCode::NOT_FOUND,
+            ProxyError::MethodNotAllowed { .. } => StatusCode::METHOD_NOT_ALLOWED,
+            ProxyError::Conflict { .. } => StatusCode::CONFLICT,
+            ProxyError::NoUpstreamKey => StatusCode::SERVICE_UNAVAILABLE,
+            ProxyError::UpstreamUnavailable(_) | ProxyError::UpstreamInterrupted(_) => StatusCode::BAD_GATEWAY,
+            ProxyError::UpstreamTimeout => StatusCode::GATEWAY_TIMEOUT,
+            ProxyError::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable, machine-readable code of the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProxyError::Authorization(e) => e.code(),
+            ProxyError::InvalidRequest { .. } => "invalid_request",
+            ProxyError::UnknownUrl(_) => "unknown_url",
+            ProxyError::MethodNotAllowed { .. } => "method_not_allowed",
+            ProxyError::NotFound { code, .. } | ProxyError::Conflict { code, .. } => code,
+            ProxyError::NoUpstreamKey => "no_upstream_key",
+            ProxyError::UpstreamUnavailable(_) => "upstream_unavailable",
+            ProxyError::UpstreamTimeout => "upstream_timeout",
+            ProxyError::UpstreamInterrupted(_) => "upstream_interrupted",
+            ProxyError::Internal { code, .. } => code,
+        }
+    }
+
+    /// The OpenAI error `type` matching the status
+    fn error_type(&self) -> &'static str {
+        match self.status() {
+            StatusCode::UNAUTHORIZED => "authentication_error",
+            StatusCode::FORBIDDEN => "permission_error",
+            status if status.is_server_error() => "server_error",
+            _ => "invalid_request_error",
+        }
+    }
+
+    fn body(&self) -> serde_json::Value {
+        let param = match self {
+            ProxyError::InvalidRequest { param, .. } => *param,
+            _ => None,
+        };
+
+        serde_json::json!({
+            "error": {
+                "message": self.to_string(),
+                "type": self.error_type(),
+                "code": self.code(),
+                "param": param,
+            }
+        })
+    }
+
+    /// Renders the error as a server-sent event, for streams that already have a status
+    pub fn sse_event(&self) -> String {
+        format!("data: {}\n\n", self.body())
+    }
+}
+
+impl From<AuthorizationError> for ProxyError {
+    fn from(e: AuthorizationError) -> Self {
+        ProxyError::Authorization(e)
+    }
+}
+
+impl From<reqwest::Error> for ProxyError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            ProxyError::UpstreamTimeout
+        } else {
+            ProxyError::UpstreamUnavailable(e.to_string())
+        }
+    }
+}
+
+impl From<JsonRejection> for ProxyError {
+    fn from(e: JsonRejection) -> Self {
+        ProxyError::InvalidRequest { message: e.body_text(), param: None }
+    }
+}
+
+impl From<QueryRejection> for ProxyError {
+    fn from(e: QueryRejection) -> Self {
+        ProxyError::InvalidRequest { message: e.body_text(), param: None }
+    }
+}
+
+impl From<PathRejection> for ProxyError {
+    fn from(e: PathRejection) -> Self {
+        ProxyError::InvalidRequest { message: e.body_text(), param: None }
+    }
+}
+
+impl IntoResponse for ProxyError {
+    fn into_response(self) -> Response {
+        (self.status(), Json(self.body())).into_response()
+    }
+}
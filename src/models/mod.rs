@@ -1,4 +1,8 @@
+pub mod completion;
 pub mod config;
+pub mod usage;
 mod authorization;
+mod error;
 
-pub use authorization::{AuthorizationError, ValidatedAccessKey};
\ No newline at end of file
+pub use authorization::{AuthorizationError, ClientIdentity, IdentityKind, ValidatedAccessKey};
+pub use error::ProxyError;
\ No newline at end of file
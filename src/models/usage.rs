@@ -0,0 +1,41 @@
+//! Token usage models for the OpenAI Proxy Carousel
+//!
+//! This module extracts the `usage` object reported by the upstream API.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Token counts reported by the upstream API for a single request
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub struct TokenUsage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+}
+
+impl TokenUsage {
+    /// Reads the `usage` object from a JSON response body
+    pub fn from_json(value: &Value) -> Option<TokenUsage> {
+        let usage = value.get("usage")?;
+        if usage.is_null() {
+            return None;
+        }
+
+        serde_json::from_value(usage.clone()).ok()
+    }
+
+    /// Reads the `usage` object from a raw (non-streaming) response body
+    pub fn from_body(body: &[u8]) -> Option<TokenUsage> {
+        let value: Value = serde_json::from_slice(body).ok()?;
+        TokenUsage::from_json(&value)
+    }
+}
+
+/// Reads the `model` field from a request body
+pub fn requested_model(body: &[u8]) -> Option<String> {
+    let value: Value = serde_json::from_slice(body).ok()?;
+    value.get("model")?.as_str().map(str::to_string)
+}
@@ -0,0 +1,56 @@
+//! Connection information for the OpenAI Proxy Carousel
+//!
+//! [`PeerInfo`] is recorded for every connection, whichever listener accepted it, and is
+//! available to middleware and handlers through `ConnectInfo<PeerInfo>`.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{extract::connect_info::Connected, serve::IncomingStream};
+use tokio::net::TcpListener;
+
+use crate::tls::{client_certificate, TlsListener};
+
+/// The names of a verified TLS client certificate
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    pub common_name: Option<String>,
+    /// DNS, URI and email subject alternative names
+    pub subject_alt_names: Vec<String>,
+}
+
+/// The remote end of a connection
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    /// `None` for connections on a Unix domain socket
+    pub address: Option<SocketAddr>,
+    /// The client certificate presented during the TLS handshake
+    pub client_certificate: Option<Arc<ClientCertificate>>,
+}
+
+impl Connected<IncomingStream<'_, TcpListener>> for PeerInfo {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        PeerInfo {
+            address: Some(*stream.remote_addr()),
+            client_certificate: None,
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for PeerInfo {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        PeerInfo {
+            address: Some(*stream.remote_addr()),
+            client_certificate: client_certificate(stream.io()).map(Arc::new),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Connected<IncomingStream<'_, tokio::net::UnixListener>> for PeerInfo {
+    fn connect_info(_stream: IncomingStream<'_, tokio::net::UnixListener>) -> Self {
+        PeerInfo {
+            address: None,
+            client_certificate: None,
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! Redaction of logged payloads for the OpenAI Proxy Carousel
+//!
+//! Every payload is masked before it is written to disk. Bearer tokens, OpenAI style
+//! keys and the keys from the configuration are always masked; the `[redaction]`
+//! section adds preset patterns, custom regular expressions and JSON paths whose
+//! values are replaced entirely.
+
+use regex::{Captures, Regex};
+use serde_json::Value;
+use serde_json_path::JsonPath;
+
+use crate::models::config::{Config, RedactionPreset};
+
+/// Replacement used when none is configured
+pub const DEFAULT_REPLACEMENT: &str = "[REDACTED]";
+
+/// Patterns that are masked regardless of the configuration
+const SECRET_PATTERNS: &[&str] = &[r"(?i)bearer\s+[A-Za-z0-9._~+/=-]+", r"sk-[A-Za-z0-9_-]{16,}"];
+
+const EMAIL_PATTERN: &str = r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}";
+const PHONE_PATTERN: &str = r"(?:\+\d{1,3}[\s.-]?|\(|\b)\d{3}\)?[\s.-]?\d{3}[\s.-]?\d{4}\b";
+const CARD_NUMBER_PATTERN: &str = r"\b(?:\d[ -]?){12,18}\d\b";
+
+/// Errors in the `[redaction]` configuration
+#[derive(Debug, thiserror::Error)]
+pub enum RedactionError {
+    #[error("invalid redaction pattern `{pattern}`: {source}")]
+    Pattern {
+        pattern: String,
+        source: regex::Error,
+    },
+    #[error("invalid redaction JSON path `{path}`: {message}")]
+    JsonPath { path: String, message: String },
+}
+
+/// A regular expression and whether its matches must pass the Luhn check
+struct Rule {
+    regex: Regex,
+    luhn: bool,
+}
+
+/// Masks secrets and personal data in logged payloads
+pub struct Redactor {
+    rules: Vec<Rule>,
+    secrets: Vec<String>,
+    json_paths: Vec<JsonPath>,
+    replacement: String,
+}
+
+fn compile(pattern: &str) -> Result<Regex, RedactionError> {
+    Regex::new(pattern).map_err(|source| RedactionError::Pattern {
+        pattern: pattern.to_string(),
+        source,
+    })
+}
+
+/// Checks a card number candidate with the Luhn algorithm to avoid masking other numbers
+fn passes_luhn(candidate: &str) -> bool {
+    let digits: Vec<u32> = candidate.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(position, digit)| {
+            if position % 2 == 1 {
+                let doubled = digit * 2;
+                if doubled > 9 { doubled - 9 } else { doubled }
+            } else {
+                *digit
+            }
+        })
+        .sum();
+
+    sum.is_multiple_of(10)
+}
+
+impl Redactor {
+    /// Builds the redactor for `config`, masking its API, access and admin keys
+    pub fn new(config: &Config) -> Result<Self, RedactionError> {
+        let redaction = &config.redaction;
+
+        let mut rules = Vec::new();
+        for pattern in SECRET_PATTERNS {
+            rules.push(Rule { regex: compile(pattern)?, luhn: false });
+        }
+        for preset in &redaction.presets {
+            let rule = match preset {
+                RedactionPreset::Email => Rule { regex: compile(EMAIL_PATTERN)?, luhn: false },
+                RedactionPreset::Phone => Rule { regex: compile(PHONE_PATTERN)?, luhn: false },
+                RedactionPreset::CardNumber => Rule { regex: compile(CARD_NUMBER_PATTERN)?, luhn: true },
+            };
+            rules.push(rule);
+        }
+        for pattern in &redaction.patterns {
+            rules.push(Rule { regex: compile(pattern)?, luhn: false });
+        }
+
+        let json_paths = redaction
+            .json_paths
+            .iter()
+            .map(|path| {
+                JsonPath::parse(path).map_err(|e| RedactionError::JsonPath {
+                    path: path.clone(),
+                    message: e.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let secrets = config
+            .api_keys
+            .iter()
+            .chain(config.access_keys.iter())
+            .chain(config.admin_keys.iter())
+            .map(|entry| &entry.key)
+            .filter(|key| !key.is_empty())
+            .cloned()
+            .collect();
+
+        Ok(Self {
+            rules,
+            secrets,
+            json_paths,
+            replacement: redaction
+                .replacement
+                .clone()
+                .unwrap_or_else(|| DEFAULT_REPLACEMENT.to_string()),
+        })
+    }
+
+    /// Masks a text payload
+    pub fn redact_str(&self, text: &str) -> String {
+        let mut text = text.to_string();
+
+        for rule in &self.rules {
+            let replaced = rule.regex.replace_all(&text, |captures: &Captures| {
+                let matched = &captures[0];
+                if rule.luhn && !passes_luhn(matched) {
+                    matched.to_string()
+                } else {
+                    self.replacement.clone()
+                }
+            });
+            text = replaced.into_owned();
+        }
+        for secret in &self.secrets {
+            if text.contains(secret.as_str()) {
+                text = text.replace(secret.as_str(), &self.replacement);
+            }
+        }
+
+        text
+    }
+
+    /// Masks a JSON payload in place: values selected by the configured JSON paths are
+    /// replaced entirely, then every remaining string is masked like text
+    pub fn redact_value(&self, value: &mut Value) {
+        for path in &self.json_paths {
+            let pointers: Vec<String> = path
+                .query_located(value)
+                .locations()
+                .map(|location| location.to_json_pointer())
+                .collect();
+
+            for pointer in pointers {
+                if let Some(node) = value.pointer_mut(&pointer) {
+                    *node = Value::String(self.replacement.clone());
+                }
+            }
+        }
+
+        self.redact_strings(value);
+    }
+
+    fn redact_strings(&self, value: &mut Value) {
+        match value {
+            Value::String(text) => *text = self.redact_str(text),
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact_strings(item)),
+            Value::Object(fields) => fields.values_mut().for_each(|field| self.redact_strings(field)),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{providers::{Format, Toml}, Figment};
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::config::ValidationError;
+
+    fn config(redaction: &str) -> Config {
+        let toml = format!(
+            r#"
+            version = "test"
+            host = "127.0.0.1"
+            port = 8080
+            base_url = "https://api.openai.com/v1"
+            api_keys = ["sk-upstream-key"]
+            access_keys = ["pk-client-key"]
+            [redaction]
+            {redaction}
+            "#
+        );
+        Figment::from(Toml::string(&toml)).extract().unwrap()
+    }
+
+    fn redactor(redaction: &str) -> Redactor {
+        Redactor::new(&config(redaction)).unwrap()
+    }
+
+    #[test]
+    fn always_masks_credentials_and_configured_keys() {
+        let redactor = redactor("");
+
+        assert_eq!(
+            redactor.redact_str("Authorization: Bearer abc.def sk-proj-0123456789abcdefXYZ pk-client-key sk-upstream-key"),
+            "Authorization: [REDACTED] [REDACTED] [REDACTED] [REDACTED]"
+        );
+        assert_eq!(redactor.redact_str("mail me at jane@example.com"), "mail me at jane@example.com");
+    }
+
+    #[test]
+    fn masks_email_preset() {
+        let redactor = redactor(r#"presets = ["email"]"#);
+
+        assert_eq!(redactor.redact_str("mail jane.doe+ai@example.co.uk now"), "mail [REDACTED] now");
+    }
+
+    #[test]
+    fn masks_phone_preset() {
+        let redactor = redactor(r#"presets = ["phone"]"#);
+
+        assert_eq!(redactor.redact_str("call +1 555-123-4567"), "call [REDACTED]");
+        assert_eq!(redactor.redact_str("call (555) 123-4567"), "call [REDACTED]");
+        assert_eq!(redactor.redact_str("order 12345"), "order 12345");
+    }
+
+    #[test]
+    fn masks_card_numbers_passing_luhn() {
+        let redactor = redactor(r#"presets = ["card_number"]"#);
+
+        assert!(passes_luhn("4111 1111 1111 1111"));
+        assert!(!passes_luhn("4111 1111 1111 1112"));
+        assert_eq!(redactor.redact_str("card 4111 1111 1111 1111"), "card [REDACTED]");
+        assert_eq!(redactor.redact_str("card 4111-1111-1111-1111."), "card [REDACTED].");
+        assert_eq!(redactor.redact_str("order 4111 1111 1111 1112"), "order 4111 1111 1111 1112");
+    }
+
+    #[test]
+    fn masks_custom_patterns_with_custom_replacement() {
+        let redactor = redactor(
+            r#"
+            patterns = ["EMP-\\d{6}"]
+            replacement = "***"
+            "#,
+        );
+
+        assert_eq!(redactor.redact_str("employee EMP-123456"), "employee ***");
+    }
+
+    #[test]
+    fn masks_json_paths_of_nested_fields() {
+        let redactor = redactor(r#"json_paths = ["$.messages[*].content", "$.metadata.user.name"]"#);
+        let mut value = json!({
+            "model": "gpt-4o",
+            "messages": [
+                { "role": "system", "content": "be brief" },
+                { "role": "user", "content": "hello" },
+            ],
+            "metadata": { "user": { "name": "Jane", "id": 7 } },
+            "note": "Bearer abc",
+        });
+
+        redactor.redact_value(&mut value);
+        assert_eq!(
+            value,
+            json!({
+                "model": "gpt-4o",
+                "messages": [
+                    { "role": "system", "content": "[REDACTED]" },
+                    { "role": "user", "content": "[REDACTED]" },
+                ],
+                "metadata": { "user": { "name": "[REDACTED]", "id": 7 } },
+                "note": "[REDACTED]",
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_patterns_and_json_paths_at_load() {
+        let errors = config(r#"patterns = ["(unclosed"]"#).validate().unwrap_err();
+        assert!(matches!(
+            errors.as_slice(),
+            [ValidationError::Redaction(RedactionError::Pattern { pattern, .. })] if pattern == "(unclosed"
+        ));
+
+        let errors = config(r#"json_paths = ["$.messages[*"]"#).validate().unwrap_err();
+        assert!(matches!(
+            errors.as_slice(),
+            [ValidationError::Redaction(RedactionError::JsonPath { path, .. })] if path == "$.messages[*"
+        ));
+    }
+}
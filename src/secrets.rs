@@ -0,0 +1,224 @@
+//! Secret indirection for the OpenAI Proxy Carousel
+//!
+//! A key in the configuration may be a reference instead of the secret itself:
+//! `file:<path>` reads it from a file, such as a mounted Kubernetes secret, and
+//! `env:<NAME>` from an environment variable. References are resolved whenever the
+//! configuration is loaded, so rotated secrets are picked up on reload.
+//!
+//! Secrets can also be stored encrypted as `enc:v1:<base64>`: AES-256-GCM with a random
+//! nonce prepended to the ciphertext, under a master key taken from the
+//! `SECRETS_MASTER_KEY` environment variable or the file named by
+//! `SECRETS_MASTER_KEY_FILE`, so a leaked configuration file does not leak the keys.
+
+use std::{env, fs, io};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// Environment variable holding the base64 encoded 256-bit master key
+pub const MASTER_KEY_ENV: &str = "SECRETS_MASTER_KEY";
+/// Environment variable naming a file that holds the master key
+pub const MASTER_KEY_FILE_ENV: &str = "SECRETS_MASTER_KEY_FILE";
+
+const FILE_PREFIX: &str = "file:";
+const ENV_PREFIX: &str = "env:";
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const NONCE_LENGTH: usize = 12;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SecretError {
+    #[error("cannot read secret file {path}: {source}")]
+    File { path: String, source: io::Error },
+    #[error("environment variable {0} is not set")]
+    Env(String),
+    #[error("secret `{0}` is empty")]
+    Empty(String),
+    #[error("secret `{0}` contains control characters, such as a line break")]
+    ControlCharacters(String),
+    #[error("no master key, set {MASTER_KEY_ENV} or {MASTER_KEY_FILE_ENV}")]
+    NoMasterKey,
+    #[error("invalid master key: {0}")]
+    InvalidMasterKey(String),
+    #[error("cannot encrypt secret: {0}")]
+    Encrypt(String),
+    #[error("cannot decrypt secret: {0}")]
+    Decrypt(String),
+}
+
+/// Returns whether a configured value is a `file:`, `env:` or `enc:v1:` reference
+pub fn is_reference(value: &str) -> bool {
+    value.starts_with(FILE_PREFIX) || value.starts_with(ENV_PREFIX) || value.starts_with(ENCRYPTED_PREFIX)
+}
+
+fn master_key() -> Result<Key<Aes256Gcm>, SecretError> {
+    let encoded = match (env::var(MASTER_KEY_ENV), env::var(MASTER_KEY_FILE_ENV)) {
+        (Ok(encoded), _) => encoded,
+        (Err(_), Ok(path)) => fs::read_to_string(&path)
+            .map_err(|source| SecretError::File { path, source })?,
+        (Err(_), Err(_)) => return Err(SecretError::NoMasterKey),
+    };
+
+    let bytes = STANDARD
+        .decode(encoded.trim())
+        .map_err(|e| SecretError::InvalidMasterKey(e.to_string()))?;
+    if bytes.len() != 32 {
+        return Err(SecretError::InvalidMasterKey(format!(
+            "expected 32 bytes, got {}",
+            bytes.len()
+        )));
+    }
+
+    Ok(*Key::<Aes256Gcm>::from_slice(&bytes))
+}
+
+/// Generates a new base64 encoded master key
+pub fn generate_master_key() -> String {
+    STANDARD.encode(Aes256Gcm::generate_key(OsRng))
+}
+
+/// Encrypts `secret` with the master key into an `enc:v1:` value
+pub fn encrypt(secret: &str) -> Result<String, SecretError> {
+    encrypt_with(&master_key()?, secret)
+}
+
+fn encrypt_with(key: &Key<Aes256Gcm>, secret: &str) -> Result<String, SecretError> {
+    let cipher = Aes256Gcm::new(key);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, secret.as_bytes())
+        .map_err(|e| SecretError::Encrypt(e.to_string()))?;
+
+    let mut payload = nonce.to_vec();
+    payload.extend_from_slice(&ciphertext);
+    Ok(format!("{}{}", ENCRYPTED_PREFIX, STANDARD.encode(payload)))
+}
+
+fn decrypt(encoded: &str) -> Result<String, SecretError> {
+    decrypt_with(&master_key()?, encoded)
+}
+
+fn decrypt_with(key: &Key<Aes256Gcm>, encoded: &str) -> Result<String, SecretError> {
+    let payload = STANDARD
+        .decode(encoded)
+        .map_err(|e| SecretError::Decrypt(e.to_string()))?;
+    if payload.len() <= NONCE_LENGTH {
+        return Err(SecretError::Decrypt("value is too short".to_string()));
+    }
+
+    let (nonce, ciphertext) = payload.split_at(NONCE_LENGTH);
+    let plaintext = Aes256Gcm::new(key)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        // Authentication failed: wrong master key or a tampered value
+        .map_err(|_| SecretError::Decrypt("wrong master key or corrupted value".to_string()))?;
+
+    String::from_utf8(plaintext).map_err(|e| SecretError::Decrypt(e.to_string()))
+}
+
+/// Resolves a `file:`, `env:` or `enc:v1:` reference; other values are returned unchanged
+///
+/// Surrounding whitespace, such as the trailing newline of a secret file, is removed;
+/// secrets with control characters left, e.g. a file of several lines, are rejected.
+pub fn resolve(value: &str) -> Result<String, SecretError> {
+    let secret = if let Some(path) = value.strip_prefix(FILE_PREFIX) {
+        fs::read_to_string(path).map_err(|source| SecretError::File {
+            path: path.to_string(),
+            source,
+        })?
+    } else if let Some(name) = value.strip_prefix(ENV_PREFIX) {
+        env::var(name).map_err(|_| SecretError::Env(name.to_string()))?
+    } else if let Some(encoded) = value.strip_prefix(ENCRYPTED_PREFIX) {
+        decrypt(encoded)?
+    } else {
+        return Ok(value.to_string());
+    };
+
+    let secret = secret.trim();
+    if secret.is_empty() {
+        return Err(SecretError::Empty(value.to_string()));
+    }
+    if secret.chars().any(char::is_control) {
+        return Err(SecretError::ControlCharacters(value.to_string()));
+    }
+
+    Ok(secret.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decrypt_value(key: &Key<Aes256Gcm>, value: &str) -> Result<String, SecretError> {
+        decrypt_with(key, value.strip_prefix(ENCRYPTED_PREFIX).unwrap())
+    }
+
+    #[test]
+    fn encrypted_secret_round_trips() {
+        let key = Aes256Gcm::generate_key(OsRng);
+        let encrypted = encrypt_with(&key, "sk-upstream").unwrap();
+
+        assert!(is_reference(&encrypted));
+        assert_ne!(encrypted, encrypt_with(&key, "sk-upstream").unwrap());
+        assert_eq!(decrypt_value(&key, &encrypted).unwrap(), "sk-upstream");
+    }
+
+    #[test]
+    fn rejects_tampered_ciphertext() {
+        let key = Aes256Gcm::generate_key(OsRng);
+        let encrypted = encrypt_with(&key, "sk-upstream").unwrap();
+        let mut payload = STANDARD.decode(encrypted.strip_prefix(ENCRYPTED_PREFIX).unwrap()).unwrap();
+        let last = payload.len() - 1;
+        payload[last] ^= 1;
+
+        let result = decrypt_with(&key, &STANDARD.encode(&payload));
+        assert!(matches!(result, Err(SecretError::Decrypt(_))));
+        let result = decrypt_with(&key, &STANDARD.encode(&payload[..NONCE_LENGTH]));
+        assert!(matches!(result, Err(SecretError::Decrypt(_))));
+    }
+
+    #[test]
+    fn rejects_wrong_master_key() {
+        let encrypted = encrypt_with(&Aes256Gcm::generate_key(OsRng), "sk-upstream").unwrap();
+
+        let result = decrypt_value(&Aes256Gcm::generate_key(OsRng), &encrypted);
+        assert!(matches!(result, Err(SecretError::Decrypt(_))));
+    }
+
+    #[test]
+    fn resolves_file_references() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("key");
+        fs::write(&path, "sk-from-file\n").unwrap();
+
+        assert_eq!(resolve(&format!("file:{}", path.display())).unwrap(), "sk-from-file");
+
+        let missing = directory.path().join("missing");
+        let result = resolve(&format!("file:{}", missing.display()));
+        assert!(matches!(result, Err(SecretError::File { .. })));
+
+        fs::write(&path, " \n").unwrap();
+        let result = resolve(&format!("file:{}", path.display()));
+        assert!(matches!(result, Err(SecretError::Empty(_))));
+
+        fs::write(&path, "sk-first\nsk-second\n").unwrap();
+        let result = resolve(&format!("file:{}", path.display()));
+        assert!(matches!(result, Err(SecretError::ControlCharacters(_))));
+    }
+
+    #[test]
+    fn resolves_env_references() {
+        env::set_var("OPENAI_PROXY_TEST_SECRET", "sk-from-env");
+
+        assert_eq!(resolve("env:OPENAI_PROXY_TEST_SECRET").unwrap(), "sk-from-env");
+        let result = resolve("env:OPENAI_PROXY_TEST_SECRET_MISSING");
+        assert!(matches!(result, Err(SecretError::Env(name)) if name == "OPENAI_PROXY_TEST_SECRET_MISSING"));
+    }
+
+    #[test]
+    fn returns_plain_values_unchanged() {
+        assert!(!is_reference("sk-plain"));
+        assert_eq!(resolve("sk-plain").unwrap(), "sk-plain");
+    }
+}
@@ -0,0 +1,91 @@
+//! Graceful shutdown for the OpenAI Proxy Carousel
+//!
+//! SIGTERM or SIGINT first makes readiness fail, so that load balancers stop routing new
+//! requests to the proxy during `shutdown_pre_stop_delay_secs`. The shutdown token in
+//! [`State`](crate::state::State) is then cancelled: the listeners stop accepting
+//! connections, while requests and streams already in flight may finish for up to
+//! `shutdown_drain_timeout_secs`. The usage and activity logs are flushed before the
+//! process exits.
+
+use std::time::Duration;
+
+use crate::state::State as ProxyState;
+
+/// How long readiness fails before connections are refused unless configured otherwise
+pub const DEFAULT_PRE_STOP_DELAY: Duration = Duration::from_secs(5);
+/// How long in-flight requests may take to finish unless configured otherwise
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Waits for SIGTERM or SIGINT
+async fn termination_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => log::info!("SIGTERM received"),
+                    _ = tokio::signal::ctrl_c() => log::info!("SIGINT received"),
+                }
+                return;
+            }
+            Err(e) => log::error!("failed to listen for SIGTERM: {}", e),
+        }
+    }
+
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        log::error!("failed to listen for SIGINT: {}", e);
+        std::future::pending::<()>().await;
+    }
+    log::info!("SIGINT received");
+}
+
+/// Fails readiness on the first termination signal, then cancels the shutdown token
+/// after the pre-stop delay or on a second signal
+pub fn spawn_signal_handler(state: ProxyState) {
+    tokio::spawn(async move {
+        termination_signal().await;
+        state.draining.cancel();
+
+        let delay = pre_stop_delay(&state);
+        if !delay.is_zero() {
+            log::info!("readiness failing, refusing new connections in {}s", delay.as_secs());
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = termination_signal() => log::info!("refusing new connections now"),
+            }
+        }
+        state.shutdown.cancel();
+    });
+}
+
+/// The configured pre-stop delay
+pub fn pre_stop_delay(state: &ProxyState) -> Duration {
+    state
+        .config
+        .read()
+        .unwrap()
+        .shutdown_pre_stop_delay_secs
+        .map_or(DEFAULT_PRE_STOP_DELAY, Duration::from_secs)
+}
+
+/// The configured drain timeout
+pub fn drain_timeout(state: &ProxyState) -> Duration {
+    state
+        .config
+        .read()
+        .unwrap()
+        .shutdown_drain_timeout_secs
+        .map_or(DEFAULT_DRAIN_TIMEOUT, Duration::from_secs)
+}
+
+/// Waits until the queued usage and activity records are written
+pub async fn flush_logs(state: &ProxyState) {
+    if let Some(recorder) = &state.usage_recorder {
+        recorder.flush().await;
+    }
+    if let Some(logger) = &state.activity_logger {
+        logger.flush().await;
+    }
+}
@@ -1,14 +1,78 @@
-use std::sync::{Arc, RwLock};
-use crate::{key_manager::KeyManager, models::config::Config};
+use std::{sync::{Arc, RwLock}, time::{Duration, Instant}};
+use tokio_util::sync::CancellationToken;
+use crate::{
+    activity_log::ActivityLogger,
+    jwt::JwtVerifier,
+    key_manager::KeyManager,
+    metrics::Metrics,
+    models::config::Config,
+    storage::{UsageRecorder, UsageStore},
+};
+
+/// Time to wait for a connection to the upstream API when not configured
+const DEFAULT_UPSTREAM_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time the upstream API may stay silent when not configured, long enough for slow models
+const DEFAULT_UPSTREAM_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Builds the client shared by all upstream requests
+///
+/// The read timeout applies to each read rather than to the whole response, so that
+/// long streams are not cut off as long as chunks keep arriving.
+fn upstream_client(config: &Config) -> reqwest::Client {
+    reqwest::Client::builder()
+        .connect_timeout(
+            config
+                .upstream_connect_timeout_secs
+                .map_or(DEFAULT_UPSTREAM_CONNECT_TIMEOUT, Duration::from_secs),
+        )
+        .read_timeout(config.upstream_timeout_secs.map_or(DEFAULT_UPSTREAM_TIMEOUT, Duration::from_secs))
+        .build()
+        .expect("the TLS backend of the HTTP client can be initialized")
+}
 
 pub struct State {
     pub config: Arc<RwLock<Config>>,
     pub key_manager: Arc<RwLock<KeyManager>>,
+    pub usage_store: Option<Arc<dyn UsageStore>>,
+    pub usage_recorder: Option<UsageRecorder>,
+    pub activity_logger: Option<ActivityLogger>,
+    pub jwt_verifier: Option<Arc<JwtVerifier>>,
+    /// Client for the upstream API, with the configured timeouts
+    pub upstream: reqwest::Client,
+    /// Cancelled when the listeners stop accepting connections
+    pub shutdown: CancellationToken,
+    /// Cancelled when readiness starts failing, before `shutdown`
+    pub draining: CancellationToken,
+    pub started_at: Instant,
+    pub metrics: Arc<Metrics>,
 }
 
 impl State {
-    pub async fn new(config: Config, km: KeyManager) -> Self {
-        Self{ config: Arc::new(RwLock::new(config)), key_manager: Arc::new(RwLock::new(km)) }
+    pub async fn new(
+        config: Config,
+        km: KeyManager,
+        usage_store: Option<Arc<dyn UsageStore>>,
+        activity_logger: Option<ActivityLogger>,
+    ) -> Self {
+        let usage_recorder = usage_store.clone().map(UsageRecorder::spawn);
+        let jwt_verifier = config.auth.jwt.clone().map(|jwt| Arc::new(JwtVerifier::new(jwt)));
+        let upstream = upstream_client(&config);
+        // Cancelling `shutdown` directly, e.g. when a listener fails, also fails readiness
+        let shutdown = CancellationToken::new();
+
+        Self{
+            config: Arc::new(RwLock::new(config)),
+            key_manager: Arc::new(RwLock::new(km)),
+            usage_store,
+            usage_recorder,
+            activity_logger,
+            jwt_verifier,
+            upstream,
+            draining: shutdown.child_token(),
+            shutdown,
+            started_at: Instant::now(),
+            metrics: Arc::new(Metrics::new().expect("metrics are registered once")),
+        }
     }
 }
 
@@ -18,6 +82,15 @@ impl Clone for State
         State {
             config: self.config.clone(),
             key_manager: self.key_manager.clone(),
+            usage_store: self.usage_store.clone(),
+            usage_recorder: self.usage_recorder.clone(),
+            activity_logger: self.activity_logger.clone(),
+            jwt_verifier: self.jwt_verifier.clone(),
+            upstream: self.upstream.clone(),
+            shutdown: self.shutdown.clone(),
+            draining: self.draining.clone(),
+            started_at: self.started_at,
+            metrics: self.metrics.clone(),
         }
     }
-}
+}
\ No newline at end of file
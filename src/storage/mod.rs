@@ -0,0 +1,153 @@
+//! Usage storage for the OpenAI Proxy Carousel
+//!
+//! Every proxied request produces a [`UsageRecord`]. Records are handed to a
+//! [`UsageRecorder`], which writes them to a [`UsageStore`] on a background task so
+//! that the request handler never waits on the database.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+
+mod sqlite;
+
+pub use sqlite::SqliteUsageStore;
+
+/// Number of records that may wait for the writer before new ones are dropped
+const RECORDER_CAPACITY: usize = 1024;
+
+/// Errors returned by a usage store
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("sqlite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("storage task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+/// A single proxied request
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageRecord {
+    pub timestamp: DateTime<Utc>,
+    /// Fingerprint of the client access key, `None` for unauthenticated requests
+    pub access_key: Option<String>,
+    /// Fingerprint of the upstream API key the request was sent with
+    pub upstream_key_hash: String,
+    pub model: Option<String>,
+    pub status: u16,
+    pub latency_ms: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    /// Cost in USD, zero when the model has no configured pricing
+    pub cost: f64,
+}
+
+/// Dimension used to aggregate usage records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageGrouping {
+    AccessKey,
+    Model,
+    Day,
+}
+
+impl UsageGrouping {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UsageGrouping::AccessKey => "key",
+            UsageGrouping::Model => "model",
+            UsageGrouping::Day => "day",
+        }
+    }
+}
+
+/// Restricts which records take part in an aggregation
+#[derive(Debug, Clone, Default)]
+pub struct UsageFilter {
+    /// Inclusive lower bound on the record timestamp
+    pub from: Option<DateTime<Utc>>,
+    /// Exclusive upper bound on the record timestamp
+    pub to: Option<DateTime<Utc>>,
+    pub access_key: Option<String>,
+    pub model: Option<String>,
+}
+
+/// Totals for one group of usage records
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageAggregate {
+    pub group: String,
+    pub requests: u64,
+    pub errors: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    pub cost: f64,
+    pub avg_latency_ms: f64,
+}
+
+/// Durable storage for usage records
+#[async_trait]
+pub trait UsageStore: Send + Sync {
+    /// Persists a single record
+    async fn insert(&self, record: &UsageRecord) -> Result<(), StorageError>;
+
+    /// Aggregates the records matching `filter` by `grouping`
+    async fn aggregate(
+        &self,
+        grouping: UsageGrouping,
+        filter: &UsageFilter,
+    ) -> Result<Vec<UsageAggregate>, StorageError>;
+}
+
+enum RecorderMessage {
+    Record(UsageRecord),
+    /// Answered once every record queued before it is stored
+    Flush(oneshot::Sender<()>),
+}
+
+/// Queues usage records for a background writer
+#[derive(Clone)]
+pub struct UsageRecorder {
+    sender: mpsc::Sender<RecorderMessage>,
+}
+
+impl UsageRecorder {
+    /// Starts the background writer for `store`
+    pub fn spawn(store: Arc<dyn UsageStore>) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<RecorderMessage>(RECORDER_CAPACITY);
+
+        tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                match message {
+                    RecorderMessage::Record(record) => {
+                        if let Err(e) = store.insert(&record).await {
+                            log::error!("failed to store usage record: {}", e);
+                        }
+                    }
+                    RecorderMessage::Flush(done) => {
+                        _ = done.send(());
+                    }
+                }
+            }
+        });
+
+        Self { sender }
+    }
+
+    /// Queues a record without waiting; the record is dropped if the writer is behind
+    pub fn record(&self, record: UsageRecord) {
+        if let Err(e) = self.sender.try_send(RecorderMessage::Record(record)) {
+            log::warn!("usage record dropped: {}", e);
+        }
+    }
+
+    /// Waits until the records queued so far are stored
+    pub async fn flush(&self) {
+        let (done, stored) = oneshot::channel();
+        if self.sender.send(RecorderMessage::Flush(done)).await.is_ok() {
+            _ = stored.await;
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! SQLite implementation of the usage store
+
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::SecondsFormat;
+use rusqlite::{params, params_from_iter, Connection};
+
+use super::{StorageError, UsageAggregate, UsageFilter, UsageGrouping, UsageRecord, UsageStore};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS usage (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp TEXT NOT NULL,
+        access_key TEXT,
+        upstream_key_hash TEXT NOT NULL,
+        model TEXT,
+        status INTEGER NOT NULL,
+        latency_ms INTEGER NOT NULL,
+        prompt_tokens INTEGER NOT NULL DEFAULT 0,
+        completion_tokens INTEGER NOT NULL DEFAULT 0,
+        total_tokens INTEGER NOT NULL DEFAULT 0,
+        cost REAL NOT NULL DEFAULT 0
+    );
+    CREATE INDEX IF NOT EXISTS usage_timestamp ON usage (timestamp);
+";
+
+/// Usage store backed by a single SQLite database file
+///
+/// rusqlite is synchronous, so every query runs on the blocking thread pool.
+pub struct SqliteUsageStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteUsageStore {
+    /// Opens (or creates) the database at `path` and applies the schema
+    pub fn open(path: &str) -> Result<Self, StorageError> {
+        let connection = Connection::open(path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    async fn with_connection<T, F>(&self, f: F) -> Result<T, StorageError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T, rusqlite::Error> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let connection = connection.lock().unwrap();
+            f(&connection)
+        })
+        .await?;
+
+        Ok(result?)
+    }
+}
+
+#[async_trait]
+impl UsageStore for SqliteUsageStore {
+    async fn insert(&self, record: &UsageRecord) -> Result<(), StorageError> {
+        let record = record.clone();
+
+        self.with_connection(move |connection| {
+            connection.execute(
+                "INSERT INTO usage (timestamp, access_key, upstream_key_hash, model, status,
+                    latency_ms, prompt_tokens, completion_tokens, total_tokens, cost)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![
+                    record.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+                    record.access_key,
+                    record.upstream_key_hash,
+                    record.model,
+                    record.status,
+                    record.latency_ms,
+                    record.prompt_tokens,
+                    record.completion_tokens,
+                    record.total_tokens,
+                    record.cost,
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn aggregate(
+        &self,
+        grouping: UsageGrouping,
+        filter: &UsageFilter,
+    ) -> Result<Vec<UsageAggregate>, StorageError> {
+        let group_expression = match grouping {
+            UsageGrouping::AccessKey => "COALESCE(access_key, 'anonymous')",
+            UsageGrouping::Model => "COALESCE(model, 'unknown')",
+            UsageGrouping::Day => "substr(timestamp, 1, 10)",
+        };
+
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        if let Some(from) = filter.from {
+            conditions.push("timestamp >= ?");
+            values.push(from.to_rfc3339_opts(SecondsFormat::Millis, true));
+        }
+        if let Some(to) = filter.to {
+            conditions.push("timestamp < ?");
+            values.push(to.to_rfc3339_opts(SecondsFormat::Millis, true));
+        }
+        if let Some(access_key) = &filter.access_key {
+            conditions.push("access_key = ?");
+            values.push(access_key.clone());
+        }
+        if let Some(model) = &filter.model {
+            conditions.push("model = ?");
+            values.push(model.clone());
+        }
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+
+        let query = format!(
+            "SELECT {group_expression} AS grp,
+                COUNT(*),
+                SUM(CASE WHEN status >= 400 THEN 1 ELSE 0 END),
+                SUM(prompt_tokens),
+                SUM(completion_tokens),
+                SUM(total_tokens),
+                SUM(cost),
+                AVG(latency_ms)
+             FROM usage {where_clause}
+             GROUP BY grp
+             ORDER BY grp"
+        );
+
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(&query)?;
+            let rows = statement.query_map(params_from_iter(values.iter()), |row| {
+                Ok(UsageAggregate {
+                    group: row.get(0)?,
+                    requests: row.get(1)?,
+                    errors: row.get(2)?,
+                    prompt_tokens: row.get(3)?,
+                    completion_tokens: row.get(4)?,
+                    total_tokens: row.get(5)?,
+                    cost: row.get(6)?,
+                    avg_latency_ms: row.get(7)?,
+                })
+            })?;
+
+            rows.collect()
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use super::*;
+
+    fn record(
+        timestamp: DateTime<Utc>,
+        access_key: Option<&str>,
+        model: &str,
+        status: u16,
+    ) -> UsageRecord {
+        UsageRecord {
+            timestamp,
+            access_key: access_key.map(str::to_string),
+            upstream_key_hash: "sk-...a1b2".to_string(),
+            model: Some(model.to_string()),
+            status,
+            latency_ms: 100,
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            total_tokens: 15,
+            cost: 0.5,
+        }
+    }
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, day, hour, 0, 0).unwrap()
+    }
+
+    async fn store() -> SqliteUsageStore {
+        let store = SqliteUsageStore::open(":memory:").unwrap();
+        let records = [
+            record(at(1, 8), Some("ak-1"), "gpt-4o", 200),
+            record(at(1, 23), Some("ak-2"), "gpt-4o-mini", 200),
+            record(at(2, 0), Some("ak-1"), "gpt-4o", 429),
+            record(at(3, 12), None, "gpt-4o", 200),
+        ];
+        for record in &records {
+            store.insert(record).await.unwrap();
+        }
+        store
+    }
+
+    /// Returns the group, request count, error count and total tokens of each aggregate
+    async fn totals(
+        store: &SqliteUsageStore,
+        grouping: UsageGrouping,
+        filter: UsageFilter,
+    ) -> Vec<(String, u64, u64, u64)> {
+        store
+            .aggregate(grouping, &filter)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|aggregate| (aggregate.group, aggregate.requests, aggregate.errors, aggregate.total_tokens))
+            .collect()
+    }
+
+    fn group(name: &str, requests: u64, errors: u64) -> (String, u64, u64, u64) {
+        (name.to_string(), requests, errors, requests * 15)
+    }
+
+    #[tokio::test]
+    async fn aggregates_by_key_model_and_day() {
+        let store = store().await;
+
+        assert_eq!(
+            totals(&store, UsageGrouping::AccessKey, UsageFilter::default()).await,
+            [group("ak-1", 2, 1), group("ak-2", 1, 0), group("anonymous", 1, 0)]
+        );
+        assert_eq!(
+            totals(&store, UsageGrouping::Model, UsageFilter::default()).await,
+            [group("gpt-4o", 3, 1), group("gpt-4o-mini", 1, 0)]
+        );
+        assert_eq!(
+            totals(&store, UsageGrouping::Day, UsageFilter::default()).await,
+            [group("2026-03-01", 2, 0), group("2026-03-02", 1, 1), group("2026-03-03", 1, 0)]
+        );
+
+        let aggregate = store.aggregate(UsageGrouping::Model, &UsageFilter::default()).await.unwrap().remove(0);
+        assert_eq!((aggregate.prompt_tokens, aggregate.completion_tokens), (30, 15));
+        assert!((aggregate.cost - 1.5).abs() < f64::EPSILON);
+        assert!((aggregate.avg_latency_ms - 100.0).abs() < f64::EPSILON);
+    }
+
+    #[tokio::test]
+    async fn includes_from_and_excludes_to() {
+        let store = store().await;
+
+        let filter = UsageFilter { from: Some(at(1, 23)), to: Some(at(3, 12)), ..Default::default() };
+        assert_eq!(
+            totals(&store, UsageGrouping::Day, filter).await,
+            [group("2026-03-01", 1, 0), group("2026-03-02", 1, 1)]
+        );
+
+        let filter = UsageFilter {
+            access_key: Some("ak-1".to_string()),
+            model: Some("gpt-4o".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(totals(&store, UsageGrouping::AccessKey, filter).await, [group("ak-1", 2, 1)]);
+    }
+}
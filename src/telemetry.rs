@@ -0,0 +1,67 @@
+//! OpenTelemetry tracing for the OpenAI Proxy Carousel
+//!
+//! With `[telemetry]` configured, the spans of the request path (authorization, key
+//! selection, the upstream request and the streamed response) are exported over
+//! OTLP/HTTP. Incoming W3C `traceparent` headers are continued, and the current span
+//! is propagated to the upstream in the same header.
+
+use axum::http::HeaderMap;
+use opentelemetry::{global, trace::TracerProvider as _, Context};
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{Sampler, SdkTracerProvider, Tracer},
+    Resource,
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::models::config::TelemetryConfig;
+
+const DEFAULT_SERVICE_NAME: &str = "openai-proxy";
+
+#[derive(Debug, thiserror::Error)]
+pub enum TelemetryError {
+    #[error("failed to build the OTLP exporter: {0}")]
+    Exporter(#[from] opentelemetry_otlp::ExporterBuildError),
+}
+
+/// Builds the span exporter for the configured collector
+pub fn build_provider(config: &TelemetryConfig) -> Result<SdkTracerProvider, TelemetryError> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(config.otlp_endpoint.clone())
+        .build()?;
+    let resource = Resource::builder()
+        .with_service_name(config.service_name.clone().unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string()))
+        .build();
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio.unwrap_or(1.0))));
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .with_sampler(sampler)
+        .build();
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    global::set_tracer_provider(provider.clone());
+
+    Ok(provider)
+}
+
+/// The tracer spans of the request path are exported with
+pub fn tracer(provider: &SdkTracerProvider) -> Tracer {
+    provider.tracer(DEFAULT_SERVICE_NAME)
+}
+
+/// Continues the trace of the client when the request carries a `traceparent`
+pub fn set_parent_from(span: &tracing::Span, headers: &HeaderMap) {
+    let parent: Context = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    _ = span.set_parent(parent);
+}
+
+/// Writes the `traceparent` of `span` into the upstream request headers
+pub fn inject(span: &tracing::Span, headers: &mut HeaderMap) {
+    let context = span.context();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut HeaderInjector(headers)));
+}
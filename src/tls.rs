@@ -0,0 +1,254 @@
+//! TLS termination for the OpenAI Proxy Carousel
+//!
+//! The listener accepts TCP connections and performs the rustls handshake in a
+//! separate task per connection, so a slow client cannot hold up the others; finished
+//! handshakes are handed to `axum::serve` through the [`Listener`] trait. The
+//! certificate and key are re-read when their files change, without a restart.
+//! With `tls.http2`, `h2` is offered through ALPN next to `http/1.1`.
+//!
+//! With `tls.client_auth`, client certificates are verified against the configured CA
+//! bundle during the handshake, and their subject and alternative names are made
+//! available to the authorization middleware through [`PeerInfo`](crate::peer::PeerInfo).
+
+use std::{
+    fs, io,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use axum::serve::Listener;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{
+    rustls::{
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        RootCertStore, ServerConfig,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+use x509_parser::{extensions::GeneralName, prelude::FromDer};
+
+use crate::models::config::{ClientAuthConfig, TlsConfig};
+use crate::peer::ClientCertificate;
+
+/// How often the certificate files are checked for changes
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Connections that do not finish the handshake in time are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Handshaken connections waiting for `axum::serve` to pick them up
+const ACCEPT_BACKLOG: usize = 128;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("{path}: {source}")]
+    Read { path: String, source: io::Error },
+    #[error("{path}: {message}")]
+    Pem { path: String, message: String },
+    #[error("{path}: no certificate found")]
+    NoCertificate { path: String },
+    #[error("invalid certificate or key: {0}")]
+    Rustls(#[from] tokio_rustls::rustls::Error),
+    #[error("{path}: invalid client CA bundle: {message}")]
+    ClientCa { path: String, message: String },
+}
+
+fn read(path: &str) -> Result<Vec<u8>, TlsError> {
+    fs::read(path).map_err(|source| TlsError::Read { path: path.to_string(), source })
+}
+
+fn load_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certificates = CertificateDer::pem_slice_iter(&read(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| TlsError::Pem { path: path.to_string(), message: e.to_string() })?;
+    if certificates.is_empty() {
+        return Err(TlsError::NoCertificate { path: path.to_string() });
+    }
+
+    Ok(certificates)
+}
+
+/// Verifies client certificates against the CA bundle, optionally letting clients
+/// without a certificate through to the other authentication methods
+fn client_verifier(
+    client_auth: &ClientAuthConfig,
+) -> Result<Arc<dyn tokio_rustls::rustls::server::danger::ClientCertVerifier>, TlsError> {
+    let ca_error = |message: String| TlsError::ClientCa {
+        path: client_auth.ca_path.clone(),
+        message,
+    };
+
+    let mut roots = RootCertStore::empty();
+    for certificate in load_certificates(&client_auth.ca_path)? {
+        roots.add(certificate).map_err(|e| ca_error(e.to_string()))?;
+    }
+
+    let builder = WebPkiClientVerifier::builder(Arc::new(roots));
+    let builder = if client_auth.required { builder } else { builder.allow_unauthenticated() };
+    builder.build().map_err(|e| ca_error(e.to_string()))
+}
+
+/// Reads the subject and alternative names of the verified client certificate, if any
+pub fn client_certificate(stream: &TlsStream<TcpStream>) -> Option<ClientCertificate> {
+    let der = stream.get_ref().1.peer_certificates()?.first()?;
+    let (_, certificate) = x509_parser::certificate::X509Certificate::from_der(der).ok()?;
+
+    let common_name = certificate
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|name| name.as_str().ok())
+        .map(str::to_string);
+    let subject_alt_names = certificate
+        .subject_alternative_name()
+        .ok()
+        .flatten()
+        .map(|extension| {
+            extension
+                .value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(name) | GeneralName::URI(name) | GeneralName::RFC822Name(name) => {
+                        Some(name.to_string())
+                    }
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Some(ClientCertificate { common_name, subject_alt_names })
+}
+
+/// Builds the rustls configuration from the certificate chain and key files
+pub fn load_server_config(tls: &TlsConfig) -> Result<ServerConfig, TlsError> {
+    let pem_error = |path: &str, e: tokio_rustls::rustls::pki_types::pem::Error| TlsError::Pem {
+        path: path.to_string(),
+        message: e.to_string(),
+    };
+
+    let certificates = load_certificates(&tls.cert_path)?;
+    let key = PrivateKeyDer::from_pem_slice(&read(&tls.key_path)?)
+        .map_err(|e| pem_error(&tls.key_path, e))?;
+
+    let builder = ServerConfig::builder();
+    let builder = match &tls.client_auth {
+        Some(client_auth) => builder.with_client_cert_verifier(client_verifier(client_auth)?),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_single_cert(certificates, key)?;
+    config.alpn_protocols = if tls.http2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+
+    Ok(config)
+}
+
+/// A TCP listener that terminates TLS
+pub struct TlsListener {
+    receiver: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    /// Starts accepting TLS connections on `listener` and watching the certificate files
+    pub fn new(listener: TcpListener, tls: TlsConfig) -> Result<Self, TlsError> {
+        let local_addr = listener.local_addr().map_err(|source| TlsError::Read {
+            path: tls.cert_path.clone(),
+            source,
+        })?;
+        let server_config = Arc::new(RwLock::new(Arc::new(load_server_config(&tls)?)));
+        let (sender, receiver) = mpsc::channel(ACCEPT_BACKLOG);
+
+        spawn_certificate_reloader(tls, server_config.clone());
+
+        tokio::spawn(async move {
+            loop {
+                // Stop accepting once `axum::serve` has dropped the listener
+                let accepted = tokio::select! {
+                    accepted = listener.accept() => accepted,
+                    _ = sender.closed() => break,
+                };
+                let (stream, address) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        log::error!("failed to accept connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        continue;
+                    }
+                };
+
+                let acceptor = TlsAcceptor::from(server_config.read().unwrap().clone());
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            _ = sender.send((stream, address)).await;
+                        }
+                        Ok(Err(e)) => log::debug!("TLS handshake with {} failed: {}", address, e),
+                        Err(_) => log::debug!("TLS handshake with {} timed out", address),
+                    }
+                });
+            }
+        });
+
+        Ok(Self { receiver, local_addr })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.receiver.recv().await {
+            Some(accepted) => accepted,
+            // The accept loop never ends, but do not spin if it ever does
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+fn modified(tls: &TlsConfig) -> Option<(SystemTime, SystemTime)> {
+    let modified = |path: &str| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    Some((modified(&tls.cert_path)?, modified(&tls.key_path)?))
+}
+
+/// Reloads the certificate when its files change, keeping the current one if the new
+/// files are invalid, e.g. because only one of them was replaced so far
+fn spawn_certificate_reloader(tls: TlsConfig, server_config: Arc<RwLock<Arc<ServerConfig>>>) {
+    tokio::spawn(async move {
+        let mut loaded = modified(&tls);
+        let mut interval = tokio::time::interval(RELOAD_CHECK_INTERVAL);
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            let current = modified(&tls);
+            if current.is_none() || current == loaded {
+                continue;
+            }
+
+            loaded = current;
+            match load_server_config(&tls) {
+                Ok(config) => {
+                    *server_config.write().unwrap() = Arc::new(config);
+                    log::info!("TLS certificate reloaded from {}", tls.cert_path);
+                }
+                Err(e) => log::error!("failed to reload TLS certificate, keeping the current one: {}", e),
+            }
+        }
+    });
+}